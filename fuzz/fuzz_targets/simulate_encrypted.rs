@@ -23,5 +23,5 @@ fuzz_target!(|data: &[u8]| {
         ChunkDescriptor::new(12).unwrap(),
         ChunkDescriptor::new(10).unwrap(),
     ];
-    simulate_encrypted(descriptors.as_ref(), ic.as_ref(), rc.as_ref(), data.as_ref(), &mut output);
+    simulate_encrypted(descriptors.as_ref(), ic.as_ref(), rc.as_ref(), data.as_ref(), false, &mut output);
 });
@@ -1,56 +1,134 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use wireshark_definitions::TreePresenter;
-use wireshark_epan_adapter::{Dissector, dissector::{Packet, Tree, PacketInfo}};
-use tezos_conversation::{Context, Identity, proof_of_work::DEFAULT_TARGET};
-use std::collections::BTreeMap;
+use wireshark_definitions::{TreePresenter, TreeLeaf};
+use wireshark_epan_adapter::{
+    Dissector, Preferences,
+    dissector::{Packet, Tree, PacketInfo, Column},
+};
+use tezos_conversation::{
+    Context, Identity, KeyLog, MessageCache, Protocol, proof_of_work::DEFAULT_TARGET,
+};
+use std::{collections::BTreeMap, path::Path, env, fs};
 
 pub struct TezosDissector {
-    identity: Option<(Identity, String)>,
+    // All identities the user provided, each of them will be tried
+    // against every new conversation, the first that fits is used.
+    identities: Vec<(Identity, String)>,
+    // Per conversation keys, allows to decrypt without the secret key of the node.
+    key_log: Option<(KeyLog, String)>,
+    // The proof of work target the connection messages are checked against.
+    pow_target: f64,
+    // Whether the proof of work is checked in the conversations on the tezos port
+    // and chosen by Decode As, otherwise any proof of work is accepted there.
+    pow_check_forced: bool,
+    // The protocol the operations are decoded with, if none, they are shown as hex.
+    protocol: Option<Protocol>,
     // Each pair of endpoints has its own context.
     // The pair is unordered,
     // so A talk to B is the same conversation as B talks to A.
     // The key is just pointer in memory, so it is invalid when capturing session is closed.
     contexts: BTreeMap<usize, Context>,
+    // The decoded messages of all conversations, limited by the total size.
+    cache: MessageCache,
 }
 
 impl TezosDissector {
     pub fn new() -> Self {
         TezosDissector {
-            identity: None,
+            identities: Vec::new(),
+            key_log: None,
+            pow_target: DEFAULT_TARGET,
+            pow_check_forced: true,
+            protocol: Some(Protocol::Proto007),
             contexts: BTreeMap::new(),
+            cache: MessageCache::new(),
         }
     }
 }
 
 impl Dissector for TezosDissector {
-    // This method called by the wireshark when the user choose the identity file.
-    fn prefs_update(&mut self, filenames: Vec<&str>) {
-        if let Some(identity_path) = filenames.first().cloned() {
-            if !identity_path.is_empty() {
-                // read the identity from the file
-                self.identity = Identity::from_path(identity_path)
-                    .map_err(|e| {
-                        log::error!("Identity: {}", e);
-                        e
-                    })
-                    .map(|i| (i, identity_path.to_owned()))
-                    .ok();
-            }
+    // This method called by the wireshark when the user choose the identity file
+    // or the key log file, or change the proof of work target or the protocol.
+    // The identity preference might contain several paths separated by the system path separator,
+    // each path is either an identity file, or a directory with identity files.
+    fn prefs_update(&mut self, preferences: &Preferences) {
+        let pow_target = match preferences.uint("pow_target") {
+            // the target is the number of leading zero bits, it cannot exceed the hash size
+            Some(pow_target) if pow_target < 256 => pow_target as f64,
+            Some(pow_target) => {
+                log::error!("Proof of work target: {} is too big", pow_target);
+                self.pow_target
+            },
+            None => DEFAULT_TARGET,
+        };
+        let pow_check_forced = preferences.bool("pow_check_forced").unwrap_or(true);
+        // the protocol affects only how the messages are shown, the contexts remain valid
+        // the value of the preference is the number of the protocol, 0 is unknown
+        self.protocol = match preferences.enumeration("protocol") {
+            Some(0) => None,
+            Some(5) => Some(Protocol::Proto005),
+            Some(6) => Some(Protocol::Proto006),
+            _ => Some(Protocol::Proto007),
+        };
+        let identities = match preferences.filename("identity_json_file") {
+            Some(identity_paths) if !identity_paths.is_empty() => {
+                env::split_paths(identity_paths)
+                    .map(|path| load_identities(&path, pow_target))
+                    .flatten()
+                    .collect()
+            },
+            _ => Vec::new(),
+        };
+        let key_log = match preferences.filename("key_log_file") {
+            Some(key_log_path) if !key_log_path.is_empty() => KeyLog::from_path(key_log_path)
+                .map_err(|e| {
+                    log::error!("Key log: {}", e);
+                    e
+                })
+                .map(|k| (k, key_log_path.to_owned()))
+                .ok(),
+            _ => None,
+        };
+
+        // the conversations remember the result of applying the old keys,
+        // forget all of them, the wireshark will redissect the capture with the new keys
+        if identities != self.identities
+            || key_log != self.key_log
+            || pow_target != self.pow_target
+            || pow_check_forced != self.pow_check_forced
+        {
+            self.contexts.clear();
+            self.cache.clear();
         }
+        self.identities = identities;
+        self.key_log = key_log;
+        self.pow_target = pow_target;
+        self.pow_check_forced = pow_check_forced;
     }
 
     // This method called by the wireshark when a new packet just arrive,
     // or when the user click on the packet.
     fn consume(&mut self, root: &mut Tree, packet: &Packet, packet_info: &PacketInfo) -> usize {
-        self.consume_polymorphic::<Tree>(root, packet, packet_info)
+        self.consume_polymorphic::<Tree>(root, packet, packet_info, false)
+    }
+
+    // This method called by the wireshark when the packet goes to the tezos port,
+    // or when the user chose the dissector by Decode As.
+    fn consume_forced(
+        &mut self,
+        root: &mut Tree,
+        packet: &Packet,
+        packet_info: &PacketInfo,
+    ) -> usize {
+        self.consume_polymorphic::<Tree>(root, packet, packet_info, true)
     }
 
     // This method called by the wireshark when the user
     // closing current capturing session
     fn cleanup(&mut self) {
         self.contexts.clear();
+        self.cache.clear();
     }
 }
 
@@ -61,6 +139,7 @@ impl TezosDissector {
         root: &mut Tree,
         packet: &Packet,
         packet_info: &PacketInfo,
+        forced: bool,
     ) -> usize
     where
         T: TreePresenter,
@@ -69,14 +148,104 @@ impl TezosDissector {
         let payload = packet.payload();
         // retrieve or create a new context for the conversation
         let context_key = packet_info.context_key();
+        // the user might tell the conversation is tezos, then the proof of work is not checked,
+        // so the sandbox networks with zero target are shown, the weak proof of work is reported
+        let pow_target = if forced && !self.pow_check_forced {
+            0.0
+        } else {
+            self.pow_target
+        };
         let context = self
             .contexts
             .entry(context_key)
-            .or_insert_with(|| Context::new(DEFAULT_TARGET));
-        if context.add(self.identity.as_ref(), payload.as_ref(), packet_info, root) {
-            payload.len()
-        } else {
-            0
+            .or_insert_with(|| Context::new(pow_target));
+        let identities = &self.identities;
+        let key_log = self.key_log.as_ref();
+        let protocol = self.protocol;
+        let cache = &mut self.cache;
+        let summary = context.add(
+            identities,
+            key_log,
+            protocol,
+            cache,
+            payload.as_ref(),
+            packet_info,
+            root,
+        );
+        match summary {
+            Some(summary) => {
+                // replace the tcp defaults, so the conversation can be read from the packet list
+                packet_info.set_column(Column::Protocol, "Tezos\0");
+                packet_info.clear_column(Column::Info);
+                packet_info.append_column(Column::Info, &summary.to_string());
+                for record in summary.records() {
+                    let paths: &[&[&str]] = &[
+                        &["Message type", &record.name],
+                        &["Direction", record.direction, &record.name],
+                        &["Conversation", &record.conversation, &record.name],
+                    ];
+                    packet_info.queue_tap(paths, record.size as _);
+                    // the time from the first frame of the message to the last one
+                    let time = record.duration.as_millis() as _;
+                    packet_info.queue_tap(&[&["Transfer time, ms", &record.name]], time);
+                }
+                payload.len()
+            },
+            // the conversation is not recognized, but the user insists it is tezos,
+            // most likely the beginning of the conversation is not captured
+            None if forced => {
+                let leaf = TreeLeaf::Display(
+                    "the conversation is not recognized, the beginning of it is not captured, \
+                     or it cannot be decrypted any further",
+                );
+                root.add("tezos", 0..payload.len(), TreeLeaf::nothing())
+                    .subtree()
+                    .add("unrecognized", 0..0, leaf);
+                packet_info.set_column(Column::Protocol, "Tezos\0");
+                packet_info.clear_column(Column::Info);
+                packet_info.append_column(Column::Info, "[unrecognized]");
+                payload.len()
+            },
+            None => 0,
         }
     }
 }
+
+/// Read the identity from the file, or all identities from the directory.
+fn load_identities(path: &Path, pow_target: f64) -> Vec<(Identity, String)> {
+    if path.is_dir() {
+        let mut paths = match fs::read_dir(path) {
+            Ok(entries) => entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().map(|e| e == "json").unwrap_or(false))
+                .collect::<Vec<_>>(),
+            Err(e) => {
+                log::error!("Identity directory: {}, {}", path.display(), e);
+                Vec::new()
+            },
+        };
+        // the order of `read_dir` is platform dependent, make it stable
+        paths.sort();
+        paths
+            .iter()
+            .map(|path| load_identities(path, pow_target))
+            .flatten()
+            .collect()
+    } else {
+        Identity::from_path(path)
+            .map_err(|e| {
+                log::error!("Identity: {}, {}", path.display(), e);
+                e
+            })
+            .map(|i| {
+                let i = i.checked(pow_target);
+                for inconsistency in i.inconsistencies() {
+                    log::error!("Identity: {}, {}", path.display(), inconsistency);
+                }
+                (i, path.display().to_string())
+            })
+            .into_iter()
+            .collect()
+    }
+}
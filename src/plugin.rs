@@ -2,7 +2,10 @@
 // SPDX-License-Identifier: MIT
 
 use wireshark_definitions::FieldDescriptor;
-use wireshark_epan_adapter::{Plugin, NameDescriptor, PrefFilenameDescriptor, DissectorDescriptor};
+use wireshark_epan_adapter::{
+    Plugin, NameDescriptor, PrefDescriptor, PrefEnumValue, DissectorDescriptor, ExpertDescriptor,
+    ExpertGroup, ExpertSeverity, StatsDescriptor,
+};
 use tezos_messages::p2p::encoding::{
     ack::AckMessage, metadata::MetadataMessage, peer::PeerMessageResponse,
     connection::ConnectionMessage,
@@ -33,6 +36,8 @@ extern "C" fn plugin_register() {
         DissectorDescriptor {
             display_name: "Tezos\0",
             short_name: "tezos_tcp\0",
+            // the default port of the node
+            tcp_port: 9732,
         },
         NameDescriptor {
             name: "Tezos Protocol\0",
@@ -46,10 +51,26 @@ extern "C" fn plugin_register() {
                     name: "Conversation\0",
                     abbrev: "tezos.conversation_id\0",
                 },
+                FieldDescriptor::String {
+                    name: "Identity\0",
+                    abbrev: "tezos.identity\0",
+                },
+                FieldDescriptor::String {
+                    name: "Identity inconsistency\0",
+                    abbrev: "tezos.identity_inconsistency\0",
+                },
+                FieldDescriptor::String {
+                    name: "Unrecognized\0",
+                    abbrev: "tezos.unrecognized\0",
+                },
                 FieldDescriptor::String {
                     name: "Source\0",
                     abbrev: "tezos.source\0",
                 },
+                FieldDescriptor::String {
+                    name: "Missing data\0",
+                    abbrev: "tezos.missing_data\0",
+                },
                 FieldDescriptor::String {
                     name: "Decryption error\0",
                     abbrev: "tezos.decryption_error\0",
@@ -58,18 +79,27 @@ extern "C" fn plugin_register() {
                     name: "Decoding error\0",
                     abbrev: "tezos.decoding_error\0",
                 },
+                FieldDescriptor::String {
+                    // only for first pass
+                    name: "Buffering\0",
+                    abbrev: "tezos.buffering\0",
+                },
                 FieldDescriptor::String {
                     name: "Messages\0",
                     abbrev: "tezos.messages\0",
                 },
+                FieldDescriptor::Double {
+                    name: "Proof of work difficulty\0",
+                    abbrev: "tezos.messages.connection_message.proof_of_work_difficulty\0",
+                },
             ],
             // chunk
             &[
-                FieldDescriptor::Int64Dec {
+                FieldDescriptor::Uint64Dec {
                     name: "Chunk\0",
                     abbrev: "tezos.chunk\0",
                 },
-                FieldDescriptor::Int64Dec {
+                FieldDescriptor::Uint64Dec {
                     name: "Chunk length\0",
                     abbrev: "tezos.chunk.length\0",
                 },
@@ -82,23 +112,129 @@ extern "C" fn plugin_register() {
                     name: "Buffering\0",
                     abbrev: "tezos.chunk.buffering\0",
                 },
-                FieldDescriptor::String {
+                FieldDescriptor::Bytes {
                     name: "Message authentication code\0",
                     abbrev: "tezos.chunk.mac\0",
                 },
+                FieldDescriptor::String {
+                    name: "Skipped data\0",
+                    abbrev: "tezos.chunk.skipped\0",
+                },
             ],
         ],
-        &[PrefFilenameDescriptor {
-            name: "identity_json_file\0",
-            title: "Identity JSON file\0",
-            description: "JSON file with node identity information\0",
-        }],
+        // the anomalies shown in the expert info dialog
+        &[
+            ExpertDescriptor {
+                name: "tezos.expert.mac_mismatch\0",
+                summary: "Message authentication code mismatch\0",
+                group: ExpertGroup::Decryption,
+                severity: ExpertSeverity::Error,
+            },
+            ExpertDescriptor {
+                name: "tezos.expert.decoding_error\0",
+                summary: "Malformed message\0",
+                group: ExpertGroup::Malformed,
+                severity: ExpertSeverity::Error,
+            },
+            ExpertDescriptor {
+                name: "tezos.expert.weak_proof_of_work\0",
+                summary: "Proof of work is below the default target\0",
+                group: ExpertGroup::Security,
+                severity: ExpertSeverity::Warning,
+            },
+            ExpertDescriptor {
+                name: "tezos.expert.missing_data\0",
+                summary: "Previous segment is not captured\0",
+                group: ExpertGroup::Sequence,
+                severity: ExpertSeverity::Warning,
+            },
+            ExpertDescriptor {
+                name: "tezos.expert.unfinished_message\0",
+                summary: "Message is not finished in the capture\0",
+                group: ExpertGroup::Sequence,
+                severity: ExpertSeverity::Note,
+            },
+            ExpertDescriptor {
+                name: "tezos.expert.no_identity\0",
+                summary: "No identity can decrypt the conversation\0",
+                group: ExpertGroup::Undecoded,
+                severity: ExpertSeverity::Note,
+            },
+        ],
+        &[
+            PrefDescriptor::Filename {
+                name: "identity_json_file\0",
+                title: "Identity JSON file\0",
+                description: "JSON file with node identity information, \
+                    or directory with such files, \
+                    or several paths separated by the system path separator\0",
+            },
+            PrefDescriptor::Filename {
+                name: "key_log_file\0",
+                title: "Key log file\0",
+                description: "File with per conversation keys, \
+                    allows to decrypt without the secret key of the node\0",
+            },
+            PrefDescriptor::Uint {
+                name: "pow_target\0",
+                title: "Proof of work target\0",
+                description: "Number of leading zero bits the hash of the connection message \
+                    stamp should have, the connection message that does not meet the target \
+                    is rejected\0",
+                default: 24,
+            },
+            PrefDescriptor::Bool {
+                name: "pow_check_forced\0",
+                title: "Check proof of work on the port and Decode As\0",
+                description: "Check the proof of work of the conversations on the tezos port \
+                    and the conversations chosen by Decode As, if unchecked, \
+                    any proof of work is accepted there and the weak one is only reported\0",
+                default: true,
+            },
+            PrefDescriptor::Enum {
+                name: "protocol\0",
+                title: "Protocol\0",
+                description: "The protocol in effect, chosen by its hash, the operations \
+                    are decoded according to it, if the protocol is unknown, \
+                    the operations are shown as hex\0",
+                // the name of the value is the hash, the value is the number of the protocol
+                values: &[
+                    PrefEnumValue {
+                        name: "unknown\0",
+                        description: "Unknown, show the operations as hex\0",
+                        value: 0,
+                    },
+                    PrefEnumValue {
+                        name: "PsBabyM1eUXZseaJdmXFApDSBqj8YBfwELoxZHHW77EMcAbbwAS\0",
+                        description: "005 PsBabyM1eUXZseaJdmXFApDSBqj8YBfwELoxZHHW77EMcAbbwAS\0",
+                        value: 5,
+                    },
+                    PrefEnumValue {
+                        name: "PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb\0",
+                        description: "006 PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb\0",
+                        value: 6,
+                    },
+                    PrefEnumValue {
+                        name: "PsDELPH1Kxsxt8f9eWbxQeRxkjfbxoqM52jvs5Y5fBxWWh4ifpo\0",
+                        description: "007 PsDELPH1Kxsxt8f9eWbxQeRxkjfbxoqM52jvs5Y5fBxWWh4ifpo\0",
+                        value: 7,
+                    },
+                ],
+                default: 7,
+                radio_buttons: false,
+            },
+        ],
     )
     // declare fields needed for presenting types
     .register_type::<TezosEncoded<ConnectionMessage>>()
     .register_type::<TezosEncoded<MetadataMessage>>()
     .register_type::<TezosEncoded<AckMessage>>()
     .register_type::<TezosEncoded<PeerMessageResponse>>()
+    // count the messages by type, direction and conversation, `tshark -z tezos,tree`
+    .register_stats(StatsDescriptor {
+        abbrev: "tezos\0",
+        name: "Tezos Messages\0",
+    })
     // before this line we just create static structure that does nothing,
     // this line registers the plugin in the wireshark, o wireshark can call our dissector
     .register(Box::new(TezosDissector::new()))
@@ -0,0 +1,38 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use tezos_encoding::encoding::{Encoding, Field};
+use tezos_conversation::{Tree, simulate_decoding, simulate_estimated_decoding};
+
+/// The object nested `depth` times, every level has two integers around the nested object,
+/// so the size of each node is known only after its nested object is decoded.
+fn nested(depth: usize) -> (Encoding, usize) {
+    (0..depth).fold((Encoding::Int32, 4), |(encoding, size), _| {
+        let encoding = Encoding::Obj(vec![
+            Field::new("a", Encoding::Int32),
+            Field::new("b", encoding),
+            Field::new("c", Encoding::Int32),
+        ]);
+        (encoding, size + 8)
+    })
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("nested");
+    for &depth in &[1, 4, 16, 64] {
+        let (encoding, size) = nested(depth);
+        let data = vec![0; size];
+        group.bench_with_input(BenchmarkId::new("single_pass", depth), &depth, |b, _| {
+            b.iter(|| simulate_decoding(&encoding, &data, &mut Tree::default()))
+        });
+        // the old path, the size is estimated before decoding
+        group.bench_with_input(BenchmarkId::new("estimated", depth), &depth, |b, _| {
+            b.iter(|| simulate_estimated_decoding(&encoding, &data, &mut Tree::default()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
@@ -45,7 +45,7 @@ impl Addresses {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum Sender {
     Initiator,
     Responder,
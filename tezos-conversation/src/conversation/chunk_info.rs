@@ -18,6 +18,10 @@ struct Inner {
     // false means this chunk start a new message,
     // true means this chunk is a continuation of some message,
     continuation: bool,
+    // the message continues after this chunk, but the next chunk is not received yet
+    unfinished: bool,
+    // the data that cannot be decrypted and is skipped, not a chunk actually
+    skipped: bool,
 }
 
 impl ChunkInfo {
@@ -27,10 +31,28 @@ impl ChunkInfo {
                 start,
                 end,
                 continuation: false,
+                unfinished: false,
+                skipped: false,
             }),
         }
     }
 
+    pub fn skipped(start: usize, end: usize) -> Self {
+        ChunkInfo {
+            inner: Cell::new(Inner {
+                start,
+                end,
+                continuation: false,
+                unfinished: false,
+                skipped: true,
+            }),
+        }
+    }
+
+    pub fn is_skipped(&self) -> bool {
+        self.inner.get().skipped
+    }
+
     pub fn range(&self) -> Range<usize> {
         let inner = self.inner.get();
         inner.start..inner.end
@@ -39,12 +61,27 @@ impl ChunkInfo {
     pub fn continuation(&self) -> bool {
         self.inner.get().continuation
     }
+
+    pub fn unfinished(&self) -> bool {
+        self.inner.get().unfinished
+    }
+
+    pub fn set_unfinished(&self) {
+        let inner = self.inner.get();
+        self.inner.set(Inner {
+            unfinished: true,
+            ..inner
+        });
+    }
 }
 
 impl HasBodyRange for ChunkInfo {
     fn body(&self) -> Range<usize> {
         let range = self.range();
-        if range.start == 0 {
+        if self.is_skipped() {
+            // has no body
+            range.start..range.start
+        } else if range.start == 0 {
             // first chunk is plain, has no MAC
             (range.start + 2)..range.end
         } else {
@@ -55,9 +92,8 @@ impl HasBodyRange for ChunkInfo {
     fn set_continuation(&self) {
         let inner = self.inner.get();
         self.inner.set(Inner {
-            start: inner.start,
-            end: inner.end,
             continuation: true,
+            ..inner
         });
     }
 }
@@ -1,21 +1,31 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use wireshark_definitions::{PacketMetadata, TreePresenter, TreeLeaf};
-use tezos_encoding::encoding::HasEncoding;
+use wireshark_definitions::{PacketMetadata, TreePresenter, TreeLeaf, TCP_FLAG_FIN, TCP_FLAG_RST};
+use tezos_encoding::encoding::{HasEncoding, Encoding};
 use tezos_messages::p2p::encoding::{
     ack::AckMessage, metadata::MetadataMessage, peer::PeerMessageResponse,
     connection::ConnectionMessage,
 };
 use failure::Fail;
-use super::{addresses::Sender, direct_buffer::DecryptError, overall_buffer::ConversationBuffer};
+use std::rc::Rc;
+use super::{
+    addresses::Sender,
+    chunk_info::ChunkInfo,
+    direct_buffer::DecryptError,
+    overall_buffer::ConversationBuffer,
+    message_cache::{MessageCache, DecodedMessage},
+    summary::{Summary, MessageRecord},
+};
 use crate::{
-    identity::{Decipher, Identity, IdentityError},
-    value::{ChunkedData, Named, HasBodyRange, show},
+    identity::{Decipher, Identity, IdentityError, IdentityInconsistency},
+    key_log::KeyLog,
+    value::{ChunkedData, Decoded, Named, HasBodyRange, Protocol, show, show_object},
     range_tool::intersect,
+    proof_of_work::DEFAULT_TARGET,
 };
 
-#[derive(Debug, Eq, PartialEq, Fail)]
+#[derive(Debug, PartialEq, Fail)]
 pub enum State {
     #[fail(display = "Correct")]
     Correct,
@@ -23,6 +33,8 @@ pub enum State {
     HaveNoIdentity,
     #[fail(display = "Identity at: {} is invalid", _0)]
     IdentityInvalid(String),
+    #[fail(display = "Identity at: {} is inconsistent", _0)]
+    IdentityInconsistent(String, Vec<IdentityInconsistency>),
     #[fail(display = "Identity at: {} cannot decrypt this conversation", _0)]
     IdentityCannotDecrypt(String),
     #[fail(display = "{}", _0)]
@@ -35,6 +47,7 @@ impl State {
             &State::Correct => false,
             &State::HaveNoIdentity
             | &State::IdentityInvalid(_)
+            | &State::IdentityInconsistent(..)
             | &State::IdentityCannotDecrypt(_) => true,
             &State::DecryptError(ref e) => i == e.chunk_number,
         }
@@ -47,7 +60,13 @@ pub struct ErrorPosition {
 }
 
 pub enum ContextInner {
-    Regular(ConversationBuffer, Option<Decipher>, State),
+    // the decipher goes together with the filename of the identity that produced it
+    // and inconsistencies of the identity
+    Regular(
+        ConversationBuffer,
+        Option<(Decipher, String, Vec<IdentityInconsistency>)>,
+        State,
+    ),
     Unrecognized,
 }
 
@@ -64,7 +83,8 @@ impl ContextInner {
         &mut self,
         payload: &[u8],
         packet_info: &P,
-        identity: Option<&(Identity, String)>,
+        identities: &[(Identity, String)],
+        key_log: Option<&(KeyLog, String)>,
     ) where
         P: PacketMetadata,
     {
@@ -80,30 +100,62 @@ impl ContextInner {
                 if decipher.is_none() {
                     let buffer = &*buffer;
                     if let Some((initiator, responder)) = buffer.can_upgrade() {
-                        match identity {
-                            Some(&(ref i, ref filename)) => {
-                                *decipher = match i.decipher(initiator, responder) {
-                                    Ok(decipher) => Some(decipher),
+                        if identities.is_empty() && key_log.is_none() {
+                            *state = State::HaveNoIdentity;
+                        } else {
+                            // try each identity, the first one
+                            // that belongs to some of the parties is used,
+                            // the key log is the last resort
+                            let (mut invalid, mut inconsistent) = (None, None);
+                            for &(ref i, ref filename) in identities {
+                                let inconsistencies = i.inconsistencies().to_vec();
+                                match i.decipher(initiator, responder) {
+                                    Ok(d) => {
+                                        *decipher = Some((d, filename.clone(), inconsistencies));
+                                        break;
+                                    },
                                     Err(IdentityError::Invalid) => {
-                                        *state = State::IdentityInvalid(filename.clone());
-                                        None
+                                        invalid = Some(filename.clone());
                                     },
-                                    Err(IdentityError::CannotDecrypt) => {
-                                        *state = State::IdentityCannotDecrypt(filename.clone());
-                                        None
+                                    Err(IdentityError::Inconsistent) => {
+                                        inconsistent = Some((filename.clone(), inconsistencies));
                                     },
+                                    Err(IdentityError::CannotDecrypt) => (),
                                 }
-                            },
-                            None => {
-                                *state = State::HaveNoIdentity;
-                            },
+                            }
+                            if decipher.is_none() {
+                                if let Some(&(ref k, ref filename)) = key_log {
+                                    if let Ok(d) = k.decipher(initiator, responder) {
+                                        *decipher = Some((d, filename.clone(), Vec::new()));
+                                    }
+                                }
+                            }
+                            if decipher.is_none() {
+                                *state = match (inconsistent, invalid) {
+                                    (Some((filename, inconsistencies)), _) => {
+                                        State::IdentityInconsistent(filename, inconsistencies)
+                                    },
+                                    (None, Some(filename)) => State::IdentityInvalid(filename),
+                                    (None, None) => {
+                                        let key_log_filename =
+                                            key_log.map(|&(_, ref filename)| filename.as_str());
+                                        let filenames = identities
+                                            .iter()
+                                            .map(|&(_, ref filename)| filename.as_str())
+                                            .chain(key_log_filename)
+                                            .collect::<Vec<_>>()
+                                            .join(", ");
+                                        State::IdentityCannotDecrypt(filenames)
+                                    },
+                                };
+                            }
                         }
                     } else if buffer.direct_buffer(packet_info).chunks().len() > 1 {
                         *self = ContextInner::Unrecognized;
                         return;
                     }
                 }
-                if let &mut Some(ref decipher) = decipher {
+                if let &mut Some((ref decipher, ..)) = decipher {
                     if let Err(e) = buffer.decrypt(decipher) {
                         log::warn!("cannot decrypt {}", e);
                         match e.chunk_number {
@@ -142,6 +194,30 @@ impl ContextInner {
         }
     }
 
+    fn identity(&self) -> Option<&str> {
+        match self {
+            &ContextInner::Regular(_, Some((_, ref filename, _)), ..) => Some(filename.as_str()),
+            _ => None,
+        }
+    }
+
+    fn local(&self) -> Option<&Sender> {
+        match self {
+            &ContextInner::Regular(_, Some((ref decipher, ..)), _) => decipher.local(),
+            _ => None,
+        }
+    }
+
+    fn inconsistencies(&self) -> &[IdentityInconsistency] {
+        match self {
+            &ContextInner::Regular(_, Some((_, _, ref inconsistencies)), _) => {
+                inconsistencies.as_slice()
+            },
+            &ContextInner::Regular(_, None, State::IdentityInconsistent(_, ref i)) => i.as_slice(),
+            _ => &[],
+        }
+    }
+
     fn state(&self) -> &State {
         match self {
             &ContextInner::Regular(_, _, ref state, ..) => state,
@@ -149,6 +225,16 @@ impl ContextInner {
         }
     }
 
+    /// Returns if the packet was consumed by this context.
+    pub fn seen<P>(&self, packet_info: &P) -> bool
+    where
+        P: PacketMetadata,
+    {
+        self.buffer()
+            .direct_buffer(packet_info)
+            .has_packet(packet_info.frame_number())
+    }
+
     pub fn after<P>(&self, packet_info: &P, error_position: &ErrorPosition) -> bool
     where
         P: PacketMetadata,
@@ -161,7 +247,18 @@ impl ContextInner {
     }
 
     /// Returns if there is decryption error.
-    pub fn visualize<P, T>(&self, packet_info: &P, root: &mut T) -> Result<(), ErrorPosition>
+    /// The operations are decoded with the encoding of the given protocol,
+    /// the messages decoded after the first pass are kept in the cache
+    /// shared by all conversations, the `id` distinguishes the conversation there.
+    pub fn visualize<P, T>(
+        &self,
+        packet_info: &P,
+        protocol: Option<Protocol>,
+        id: usize,
+        cache: &mut MessageCache,
+        summary: &mut Summary,
+        root: &mut T,
+    ) -> Result<(), ErrorPosition>
     where
         P: PacketMetadata,
         T: TreePresenter,
@@ -173,6 +270,10 @@ impl ContextInner {
         let chunks = buffer.chunks();
         let state = self.state();
         let sender = self.buffer().sender(packet_info);
+        let closed = packet_info
+            .flags()
+            .map(|flags| flags & (TCP_FLAG_FIN | TCP_FLAG_RST) != 0)
+            .unwrap_or(false);
 
         let mut node = root
             .add("tezos", 0..space.len(), TreeLeaf::nothing())
@@ -182,12 +283,32 @@ impl ContextInner {
             0..0,
             TreeLeaf::Display(self.id().expect("valid context")),
         );
+        if let Some(identity) = self.identity() {
+            node.add("identity", 0..0, TreeLeaf::Display(identity));
+        }
+        for inconsistency in self.inconsistencies() {
+            let leaf = TreeLeaf::Display(inconsistency);
+            node.add("identity_inconsistency", 0..0, leaf);
+        }
 
-        let direction = match &sender {
-            &Sender::Initiator => "local",
-            &Sender::Responder => "remote",
+        // our node might be either the initiator or the responder,
+        // if it is unknown, show the role of the sender
+        let direction = match (self.local(), &sender) {
+            (Some(local), sender) if local == sender => "local",
+            (Some(_), _) => "remote",
+            (None, &Sender::Initiator) => "initiator",
+            (None, &Sender::Responder) => "responder",
         };
         node.add("source", 0..0, TreeLeaf::Display(direction));
+        if let Some(gap) = buffer.gap(packet_info.frame_number()) {
+            let message = format!(
+                "{} bytes before this segment are not captured yet, \
+                 the segment is out of order or some segment is lost",
+                gap.len()
+            );
+            node.add("missing_data", 0..0, TreeLeaf::Display(&message))
+                .expert("tezos.expert.missing_data", &message);
+        }
 
         let messages = node.add("messages", 0..0, TreeLeaf::nothing()).subtree();
 
@@ -195,20 +316,52 @@ impl ContextInner {
         for (index, chunk_info) in chunks.iter().enumerate() {
             let range = chunk_info.range();
             if range.end > space.start && range.start < space.end {
-                if state.error(index) {
-                    node.add("decryption_error", 0..0, TreeLeaf::Display(state));
+                if chunk_info.is_skipped() {
+                    let item = intersect(space, range.clone());
+                    let mut chunk_node =
+                        node.add("chunk", item.clone(), TreeLeaf::uint(index as _)).subtree();
+                    let message = format!(
+                        "{} bytes cannot be decrypted, skipped until the next authentic chunk",
+                        range.len()
+                    );
+                    chunk_node
+                        .add("skipped", item, TreeLeaf::Display(&message))
+                        .expert("tezos.expert.mac_mismatch", &message);
+                    summary.note("skipped");
+                } else if state.error(index) {
+                    // the message authentication code mismatch is the problem of the data,
+                    // the rest is the problem of the identity the user provided
+                    let expert = match state {
+                        &State::DecryptError(_) => "tezos.expert.mac_mismatch",
+                        _ => "tezos.expert.no_identity",
+                    };
+                    node.add("decryption_error", 0..0, TreeLeaf::Display(state))
+                        .expert(expert, state);
+                    summary.note("decryption error");
                     return Err(ErrorPosition {
                         sender,
                         frame_number: packet_info.frame_number(),
                     });
                 } else {
                     let item = intersect(space, range.clone());
-                    let mut chunk_node =
-                        node.add("chunk", item, TreeLeaf::dec(index as _)).subtree();
+                    let mut chunk_item = node.add("chunk", item, TreeLeaf::uint(index as _));
+                    // the connection is accepted only because the target in the preferences
+                    // is lower than the default, the node would reject it
+                    if index == 0 {
+                        let pow = self.buffer().proof_of_work_difficulty(packet_info);
+                        if let Some((difficulty, _)) = pow.filter(|&(d, _)| d < DEFAULT_TARGET) {
+                            let message = format!(
+                                "proof of work difficulty {:.2} is below the default target {}",
+                                difficulty, DEFAULT_TARGET,
+                            );
+                            chunk_item.expert("tezos.expert.weak_proof_of_work", message);
+                        }
+                    }
+                    let mut chunk_node = chunk_item.subtree();
 
-                    let length = range.len() as i64 - 2;
+                    let length = range.len() as u64 - 2;
                     let item = intersect(space, range.start..(range.start + 2));
-                    chunk_node.add("length", item, TreeLeaf::dec(length));
+                    chunk_node.add("length", item, TreeLeaf::uint(length));
 
                     if data.len() >= range.end {
                         let body_range = chunk_info.body();
@@ -228,14 +381,18 @@ impl ContextInner {
                         } else {
                             let item = intersect(space, body_range.clone());
                             chunk_node.add("buffering", item, TreeLeaf::Display("..."));
+                            summary.note("buffering");
                         }
 
                         if index > 0 {
                             let mac_range = body_range.end..range.end;
-                            let mac = hex::encode(&data[mac_range.clone()]);
+                            let mac = data[mac_range.clone()].to_vec();
                             let item = intersect(space, mac_range);
-                            chunk_node.add("mac", item, TreeLeaf::Display(mac));
+                            chunk_node.add("mac", item, TreeLeaf::bytes(mac));
                         }
+                    } else {
+                        // the rest of the chunk is in the following frames
+                        summary.note("buffering");
                     }
                 }
             }
@@ -243,18 +400,22 @@ impl ContextInner {
 
         let chunks = &chunks[..decrypted];
 
-        // first chunk which intersect with the frame
+        // first chunk which intersect with the frame, maybe only by its MAC,
         // but it might be a continuation of previous message,
-        // seek back to find the chunk that is not a continuation
+        // seek back to find the chunk that is not a continuation,
+        // on the first pass the chunk might continue the message unfinished so far
         let first_chunk = chunks
             .iter()
             .enumerate()
-            .find(|&(_, info)| info.body().end > space.start)
-            .map(|(i, info)| (i, info.continuation()))
+            .find(|&(_, info)| info.range().end > space.start)
+            .map(|(i, info)| {
+                let unfinished = i > 0 && chunks[i - 1].unfinished();
+                (i, info.continuation() || unfinished)
+            })
             .map(|(first_chunk, continuation)| {
                 if continuation {
-                    // safe to subtract 1 because the first chunk cannot be a continuation
-                    chunks[0..(first_chunk - 1)]
+                    // the first chunk cannot be a continuation, so there is such chunk
+                    chunks[0..first_chunk]
                         .iter()
                         .enumerate()
                         .rev()
@@ -272,51 +433,209 @@ impl ContextInner {
                     Some(chunked_buffer) => chunked_buffer,
                     None => return Ok(()),
                 };
+                chunked_buffer.set_protocol(protocol);
+                cache.set_protocol(protocol);
                 let mut messages = messages;
                 loop {
-                    if state.error(chunked_buffer.chunk()) {
+                    let skipped = chunks
+                        .get(chunked_buffer.chunk())
+                        .map(ChunkInfo::is_skipped)
+                        .unwrap_or(false);
+                    if state.error(chunked_buffer.chunk()) || skipped {
                         chunked_buffer.skip();
                         continue;
                     }
                     if !chunked_buffer.on(space) {
                         break;
                     }
-                    let (encoding, base) = match chunked_buffer.chunk() {
-                        0 => (ConnectionMessage::encoding(), ConnectionMessage::NAME),
-                        1 => (MetadataMessage::encoding(), MetadataMessage::NAME),
-                        2 => (AckMessage::encoding(), AckMessage::NAME),
-                        _ => (PeerMessageResponse::encoding(), PeerMessageResponse::NAME),
+                    // the frame that is not visited yet might have incomplete data,
+                    // do not cache the messages until the first pass is complete
+                    let index = chunked_buffer.chunk();
+                    let start = chunked_buffer.position();
+                    let cached = if packet_info.visited() {
+                        cache.get(id, sender, index)
+                    } else {
+                        None
                     };
-                    let temp = chunked_buffer.chunk();
-                    // if it is first chunk limit the buffer by just this one chunk,
-                    // because connection message goes in single chunk
-                    if temp == 0 {
-                        chunked_buffer
-                            .inner_mut()
-                            .push_limit(chunks[0].body().len());
-                    }
-                    match show(&mut chunked_buffer, space, &encoding, base, &mut messages) {
-                        Ok(_) => {
-                            if temp == 0 {
-                                chunked_buffer.inner_mut().pop_limit();
+                    let message = match cached {
+                        Some(message) => message,
+                        None => {
+                            let message = Rc::new(self.decode(&mut chunked_buffer, packet_info));
+                            if packet_info.visited() {
+                                cache.insert(id, sender, index, message.clone());
                             }
+                            message
                         },
-                        Err(e) => {
-                            let leaf = TreeLeaf::Display(e);
-                            node.add("decoding_error", 0..0, leaf);
+                    };
+                    // the fields decoded before the failure are shown as well
+                    message.decoded.render(space, &mut messages);
+                    message.names.iter().for_each(|name| summary.message(name));
+                    // the message is counted in the statistics once, in the frame
+                    // where its last chunk ends, the unfinished message is not counted,
+                    // its size is unknown and it is decoded again when the rest arrives
+                    if let Some((next_chunk, end)) = message.next {
+                        let last = match chunks.get(next_chunk) {
+                            Some(info) if info.body().start < end => next_chunk,
+                            _ => next_chunk - 1,
+                        };
+                        if space.contains(&(chunks[last].range().end - 1)) {
+                            let name = if message.names.is_empty() {
+                                "[malformed]".to_owned()
+                            } else {
+                                message.names.join(", ")
+                            };
+                            summary.record(MessageRecord {
+                                name,
+                                direction,
+                                conversation: self.buffer().id(),
+                                size: chunked_buffer.inner_mut().distance(start.1, end),
+                                duration: buffer
+                                    .time(start.1)
+                                    .and_then(|time| packet_info.timestamp().checked_sub(time))
+                                    .unwrap_or_default(),
+                            });
+                        }
+                    }
+                    if let Some((ref e, ref range)) = message.error {
+                        let item = intersect(space, range.clone());
+                        match (&message.next, packet_info.visited() || closed) {
+                            // the length of the message runs past the data received so far,
+                            // on the first pass the rest is in the following frames,
+                            // unless the connection is closed
+                            (&None, false) => {
+                                node.add("buffering", item, TreeLeaf::Display("..."));
+                                summary.note("buffering");
+                            },
+                            (&None, true) => {
+                                let text = "the length of the message runs past the capture";
+                                node.add("missing_data", item, TreeLeaf::Display(text))
+                                    .expert("tezos.expert.unfinished_message", text);
+                                summary.note("unfinished");
+                            },
+                            (&Some(_), _) => {
+                                node.add("decoding_error", item, TreeLeaf::Display(e))
+                                    .expert("tezos.expert.decoding_error", e);
+                                summary.note("decoding error");
+                            },
+                        }
+                    }
+                    match message.next {
+                        Some(position) => chunked_buffer.set_position(position),
+                        None => {
+                            // the rest of the message is not received yet,
+                            // the following frames continue it
+                            chunks[(index + 1)..].iter().for_each(HasBodyRange::set_continuation);
+                            if let Some(last) = chunks.last() {
+                                last.set_unfinished();
+                            }
                             break;
                         },
-                    };
-                    chunked_buffer.complete_group(temp, || {
-                        log::warn!(
-                            "ChunkedData::show did not consume full chunk, frame: {}",
-                            packet_info.frame_number()
-                        )
-                    });
+                    }
                 }
             }
         }
 
         Ok(())
     }
+
+    /// Decode the message that starts at the current chunk of the buffer,
+    /// the ranges are in the coordinates of the whole direct buffer.
+    fn decode<P>(
+        &self,
+        chunked_buffer: &mut ChunkedData<'_, ChunkInfo>,
+        packet_info: &P,
+    ) -> DecodedMessage
+    where
+        P: PacketMetadata,
+    {
+        let space = &(0..usize::MAX);
+        let (encoding, base) = match chunked_buffer.chunk() {
+            0 => (ConnectionMessage::encoding(), ConnectionMessage::NAME),
+            1 => (MetadataMessage::encoding(), MetadataMessage::NAME),
+            2 => (AckMessage::encoding(), AckMessage::NAME),
+            _ => (PeerMessageResponse::encoding(), PeerMessageResponse::NAME),
+        };
+        let temp = chunked_buffer.chunk();
+        let start = chunked_buffer.position();
+        // if it is first chunk limit the buffer by just this one chunk,
+        // because connection message goes in single chunk
+        if temp == 0 {
+            let chunks = self.buffer().direct_buffer(packet_info).chunks();
+            chunked_buffer.inner_mut().push_limit(chunks[0].body().len());
+        }
+        let mut decoded = Decoded::root();
+        let result = match (temp, &encoding) {
+            // the connection message, show the proof of work difficulty in it
+            (0, &Encoding::Obj(ref fields)) => {
+                let d = &mut decoded;
+                show_object(chunked_buffer, space, fields, base, d).map(|n| {
+                    let pow = self.buffer().proof_of_work_difficulty(packet_info);
+                    if let Some((difficulty, stamp_range)) = pow {
+                        let leaf = TreeLeaf::float(difficulty);
+                        n.add("proof_of_work_difficulty", stamp_range, leaf);
+                    }
+                })
+            },
+            _ => show(chunked_buffer, space, &encoding, base, &mut decoded),
+        };
+        // the peer message is the list of the variants, the name of the variant is the name
+        // of the only child of the each element, the element might be absent if malformed
+        let names = match temp {
+            0 => vec!["ConnectionMessage".to_owned()],
+            1 => vec!["MetadataMessage".to_owned()],
+            2 => vec!["AckMessage".to_owned()],
+            _ => decoded
+                .children()
+                .iter()
+                .filter_map(|element| element.children().first())
+                .map(|variant| variant.name().to_owned())
+                .collect(),
+        };
+        let error = match result {
+            Ok(()) => {
+                if temp == 0 {
+                    chunked_buffer.inner_mut().pop_limit();
+                }
+                None
+            },
+            Err(e) => {
+                let position = e.position().unwrap_or(start.1);
+                let error = Some((e.to_string(), position..(position + 1)));
+                // skip the malformed message, the handshake messages occupy the single chunk,
+                // the peer message is prefixed by its length
+                chunked_buffer.set_position(start);
+                if temp < 3 {
+                    chunked_buffer.skip();
+                    let next = Some(chunked_buffer.position());
+                    return DecodedMessage {
+                        decoded,
+                        names,
+                        error,
+                        next,
+                    };
+                } else if chunked_buffer.skip_dynamic().is_err() {
+                    return DecodedMessage {
+                        decoded,
+                        names,
+                        error,
+                        next: None,
+                    };
+                }
+                error
+            },
+        };
+        chunked_buffer.complete_group(temp, || {
+            log::warn!(
+                "ChunkedData::show did not consume full chunk, frame: {}",
+                packet_info.frame_number()
+            )
+        });
+        let next = Some(chunked_buffer.position());
+        DecodedMessage {
+            decoded,
+            names,
+            error,
+            next,
+        }
+    }
 }
@@ -1,11 +1,18 @@
 use wireshark_definitions::{PacketMetadata, TreePresenter};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use super::{
     context::{ContextInner, ErrorPosition},
     addresses::Sender,
+    message_cache::MessageCache,
+    summary::Summary,
 };
-use crate::identity::Identity;
+use crate::{identity::Identity, key_log::KeyLog, value::Protocol};
+
+// the contexts share the message cache, each context has its own id there
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
 
 pub struct Context {
+    id: usize,
     inner: Option<ContextInner>,
     pow_target: f64,
     incoming_frame_result: Result<(), ErrorPosition>,
@@ -15,6 +22,7 @@ pub struct Context {
 impl Context {
     pub fn new(pow_target: f64) -> Self {
         Context {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
             inner: None,
             pow_target,
             incoming_frame_result: Ok(()),
@@ -50,13 +58,23 @@ impl Context {
         }
     }
 
+    /// Consume the payload of the packet and show it on the tree,
+    /// the tcp sequence number of the packet allows to drop retransmissions
+    /// and to put the segments that arrived out of order on their places.
+    /// The operations are decoded with the encoding of the given protocol,
+    /// the decoded messages are kept in the cache, it is shared by all contexts.
+    /// Returns the summary of the frame if the packet belongs to the conversation.
+    #[allow(clippy::too_many_arguments)]
     pub fn add<P, T>(
         &mut self,
-        identity: Option<&(Identity, String)>,
+        identities: &[(Identity, String)],
+        key_log: Option<&(KeyLog, String)>,
+        protocol: Option<Protocol>,
+        cache: &mut MessageCache,
         data: &[u8],
         metadata: &P,
         output: &mut T,
-    ) -> bool
+    ) -> Option<Summary>
     where
         P: PacketMetadata,
         T: TreePresenter,
@@ -64,22 +82,27 @@ impl Context {
         let pow_target = self.pow_target;
         let inner = self.inner.get_or_insert_with(|| ContextInner::new(metadata, pow_target));
         if !metadata.visited() {
-            inner.consume(data, metadata, identity);    
+            inner.consume(data, metadata, identities, key_log);
         }
 
         // the context might become invalid if the conversation is not tezos,
-        // or if decryption error occurs
-        if !self.invalid(metadata) {
-            match self.inner.as_mut().unwrap().visualize(metadata, output) {
+        // or if decryption error occurs,
+        // also the context might be created when the frame is already visited,
+        // for example, after the contexts were reset, such frame is unknown for the context
+        if !self.invalid(metadata) && self.inner.as_ref().unwrap().seen(metadata) {
+            let mut summary = Summary::new();
+            let id = self.id;
+            let inner = self.inner.as_mut().unwrap();
+            match inner.visualize(metadata, protocol, id, cache, &mut summary, output) {
                 Ok(()) => (),
                 Err(r) => match r.sender {
                     Sender::Initiator => self.incoming_frame_result = Err(r),
                     Sender::Responder => self.outgoing_frame_result = Err(r),
                 },
             };
-            true
+            Some(summary)
         } else {
-            false
+            None
         }
     }
 }
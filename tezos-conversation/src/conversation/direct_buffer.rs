@@ -1,7 +1,7 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::{ops::Range, collections::BTreeMap};
+use std::{ops::Range, collections::BTreeMap, time::Duration};
 use bytes::Buf;
 use failure::Fail;
 use super::{addresses::Sender, chunk_info::ChunkInfo};
@@ -10,8 +10,20 @@ use crate::identity::{Decipher, NonceAddition};
 pub struct DirectBuffer {
     data: Vec<u8>,
     chunks: Vec<ChunkInfo>,
-    packets: BTreeMap<u64, Range<usize>>,
+    // the range of the `data` each frame carries and the time the frame is captured
+    packets: BTreeMap<u64, (Range<usize>, Duration)>,
     processed: usize,
+    // the nonce addition for the next chunk, differs from the number of the chunk
+    // if some chunks are skipped after the MAC mismatch
+    nonce: u64,
+    // the position where the search of the next authentic chunk should continue
+    resync: Option<usize>,
+    // tcp sequence number of the first byte of the `data`
+    base: Option<u32>,
+    // segments that arrived before some previous segment, keyed by the offset in the `data`
+    pending: BTreeMap<usize, Vec<u8>>,
+    // the ranges of the `data` that are never captured, filled with zeros
+    lost: Vec<Range<usize>>,
 }
 
 #[derive(Debug, Fail, Eq, PartialEq)]
@@ -24,7 +36,21 @@ pub struct DecryptError {
     pub chunk_number: usize,
 }
 
+enum Resync {
+    // the position and the end of the authentic chunk, its nonce addition and plain text
+    Found(Range<usize>, u64, Vec<u8>),
+    NeedMoreData,
+    NotFound,
+}
+
 impl DirectBuffer {
+    // how far after the broken chunk the next authentic chunk is searched
+    const RESYNC_WINDOW: usize = 0x1000;
+    // how many chunks might be lost
+    const RESYNC_NONCES: u64 = 4;
+    // how many bytes might wait for the missing segment, then the segment is considered lost
+    const LOSS_THRESHOLD: usize = 0x10000;
+
     pub fn new() -> Self {
         DirectBuffer {
             data: Vec::with_capacity(0x100000),
@@ -32,14 +58,120 @@ impl DirectBuffer {
             packets: BTreeMap::new(),
             // first message always decrypted
             processed: 1,
+            nonce: 0,
+            resync: None,
+            base: None,
+            pending: BTreeMap::new(),
+            lost: Vec::new(),
+        }
+    }
+
+    /// Consume the payload of the tcp segment, `seq` is the tcp sequence number of the segment.
+    /// Returns false if the segment is a retransmission of the data already consumed,
+    /// such segment is dropped.
+    /// Without the sequence number the payload is just appended.
+    pub fn consume(
+        &mut self,
+        payload: &[u8],
+        seq: Option<u32>,
+        frame_index: u64,
+        timestamp: Duration,
+    ) -> bool {
+        // the offset of the segment relative to the first consumed segment
+        let start = match seq {
+            Some(seq) => {
+                let base = *self.base.get_or_insert(seq);
+                let offset = seq.wrapping_sub(base);
+                // the segment is before the first consumed segment, it is a retransmission
+                if offset >= 0x80000000 {
+                    return false;
+                }
+                offset as usize
+            },
+            None => self.data.len(),
+        };
+        let end = start + payload.len();
+        if end <= self.data.len() || self.pending.contains_key(&start) {
+            return false;
+        }
+        self.packets.insert(frame_index, (start..end, timestamp));
+        if start > self.data.len() {
+            // the previous segment is not arrived yet, or it is lost
+            self.pending.insert(start, payload.to_vec());
+        } else {
+            // the segment might overlap with the data already consumed, take only new bytes
+            self.data
+                .extend_from_slice(&payload[(self.data.len() - start)..]);
         }
+        self.take_pending();
+
+        self.parse_chunks();
+
+        true
+    }
+
+    /// The peer acknowledged the data up to the `ack` tcp sequence number.
+    /// If the peer has the data past the missing segment, the segment is never
+    /// retransmitted, it is lost, do not wait for it.
+    pub fn acknowledged(&mut self, ack: u32) {
+        let offset = match self.base {
+            Some(base) if ack.wrapping_sub(base) < 0x80000000 => ack.wrapping_sub(base) as usize,
+            _ => return,
+        };
+        match self.pending.keys().next() {
+            Some(&first) if offset >= first => {
+                self.skip_lost(self.data.len()..first);
+                self.take_pending();
+                self.parse_chunks();
+            },
+            _ => (),
+        }
+    }
+
+    // Take the pending segments that are now in order. If too much data is waiting
+    // for the missing segment, the segment is lost, skip it and take the rest.
+    fn take_pending(&mut self) {
+        loop {
+            while let Some((&offset, _)) = self.pending.iter().next() {
+                if offset > self.data.len() {
+                    break;
+                }
+                let segment = self.pending.remove(&offset).unwrap();
+                if offset + segment.len() > self.data.len() {
+                    self.data
+                        .extend_from_slice(&segment[(self.data.len() - offset)..]);
+                }
+            }
+            let waiting = self.pending.values().map(Vec::len).sum::<usize>();
+            match self.pending.keys().next() {
+                Some(&offset) if waiting > Self::LOSS_THRESHOLD => {
+                    self.skip_lost(self.data.len()..offset)
+                },
+                _ => break,
+            }
+        }
+    }
+
+    // The chunk that overlaps the lost range cannot be read, and the position
+    // of the next chunk is unknown, mark everything from the start of the chunk
+    // to the end of the lost range as skipped.
+    fn skip_lost(&mut self, lost: Range<usize>) {
+        log::warn!("{} bytes are lost", lost.len());
+        self.parse_chunks();
+        let overlapping = self
+            .chunks
+            .iter()
+            .position(|c| c.range().end > lost.start)
+            .unwrap_or(self.chunks.len());
+        self.chunks.truncate(overlapping.max(self.processed));
+        let start = self.chunks.last().map(|r| r.range().end).unwrap_or(0);
+        self.chunks.push(ChunkInfo::skipped(start, lost.end));
+        self.data.resize(lost.end, 0);
+        self.lost.push(lost);
     }
 
-    pub fn consume(&mut self, payload: &[u8], frame_index: u64) {
-        let start = self.data.len();
-        self.data.extend_from_slice(payload);
+    fn parse_chunks(&mut self) {
         let end = self.data.len();
-        self.packets.insert(frame_index, start..end);
         let mut position = self.chunks.last().map(|r| r.range().end).unwrap_or(0);
 
         loop {
@@ -54,35 +186,145 @@ impl DirectBuffer {
         }
     }
 
+    /// The range of the data missing before the packet, if the packet arrived out of order,
+    /// or some previous packet is lost.
+    pub fn gap(&self, index: u64) -> Option<Range<usize>> {
+        self.packets.get(&index).and_then(|&(ref range, _)| {
+            if range.start > self.data.len() {
+                Some(self.data.len()..range.start)
+            } else {
+                self.lost.iter().find(|lost| lost.end == range.start).cloned()
+            }
+        })
+    }
+
+    /// Decrypt all chunks that are available. If some chunk has wrong MAC,
+    /// the chunk might be corrupted or its length might be wrong, search the next
+    /// authentic chunk nearby and skip the broken span.
+    /// Returns an error if nothing authentic is found nearby.
     pub fn decrypt(&mut self, decipher: &Decipher, sender: Sender) -> Result<(), DecryptError> {
-        if self.chunks().len() > self.processed {
-            let chunks = (&self.chunks()[self.processed..]).to_vec();
-            for chunk in chunks {
-                let chunk = chunk.range();
-                if self.data().len() >= chunk.end {
-                    let nonce = match &sender {
-                        &Sender::Initiator => NonceAddition::Initiator((self.processed - 1) as u64),
-                        &Sender::Responder => NonceAddition::Responder((self.processed - 1) as u64),
-                    };
-                    let data = &self.data()[(chunk.start + 2)..chunk.end];
-                    if let Ok(plain) = decipher.decrypt(data, nonce) {
-                        self.processed += 1;
-                        self.data_mut()[(chunk.start + 2)..(chunk.end - 16)]
-                            .clone_from_slice(plain.as_ref());
-                    } else {
-                        return Err(DecryptError {
-                            sender,
-                            chunk_number: self.processed,
-                        });
-                    }
-                } else {
-                    break;
+        while let Some(chunk) = self.chunks.get(self.processed).map(ChunkInfo::range) {
+            if self.data.len() < chunk.end {
+                break;
+            }
+            // the chunk overlaps the lost data, search the next authentic chunk after it
+            let lost = self.chunks[self.processed].is_skipped();
+            if !lost {
+                let nonce = self.nonce;
+                if let Some(plain) = self.try_decrypt(decipher, &sender, chunk.clone(), nonce) {
+                    self.accept(chunk, plain);
+                    continue;
                 }
             }
+            let error = DecryptError {
+                sender,
+                chunk_number: self.processed,
+            };
+            // if cannot decrypt the first encrypted chunk,
+            // most likely it is not the conversation of the identity, do not search
+            if self.processed == 1 && !lost {
+                return Err(error);
+            }
+            match self.resync(decipher, &sender, chunk.clone()) {
+                Resync::Found(range, nonce, plain) => {
+                    log::warn!("{}, skipped {} bytes", error, range.start - chunk.start);
+                    // the chunks after the broken one are parsed with wrong boundaries
+                    self.chunks.truncate(self.processed);
+                    self.chunks.push(ChunkInfo::skipped(chunk.start, range.start));
+                    self.processed += 1;
+                    self.chunks.push(ChunkInfo::new(range.start, range.end));
+                    self.nonce = nonce;
+                    self.resync = None;
+                    self.accept(range, plain);
+                    self.parse_chunks();
+                },
+                Resync::NeedMoreData => break,
+                Resync::NotFound => return Err(error),
+            }
         }
         Ok(())
     }
 
+    fn try_decrypt(
+        &self,
+        decipher: &Decipher,
+        sender: &Sender,
+        chunk: Range<usize>,
+        nonce: u64,
+    ) -> Option<Vec<u8>> {
+        let nonce = match sender {
+            &Sender::Initiator => NonceAddition::Initiator(nonce),
+            &Sender::Responder => NonceAddition::Responder(nonce),
+        };
+        decipher
+            .decrypt(&self.data[(chunk.start + 2)..chunk.end], nonce)
+            .ok()
+    }
+
+    fn accept(&mut self, chunk: Range<usize>, plain: Vec<u8>) {
+        self.data[(chunk.start + 2)..(chunk.end - 16)].clone_from_slice(plain.as_ref());
+        self.processed += 1;
+        self.nonce += 1;
+    }
+
+    // The broken chunk consumed the nonce, the next authentic chunk has the next nonce,
+    // unless some chunks are lost. The chunk right after the broken one is tried first,
+    // it is there if only the body of the broken chunk is corrupted,
+    // then each position in the window is tried in order.
+    // If the broken chunk overlaps the lost data, the window starts after the lost data.
+    fn resync(&mut self, decipher: &Decipher, sender: &Sender, broken: Range<usize>) -> Resync {
+        let nonces = (self.nonce + 1)..=(self.nonce + Self::RESYNC_NONCES);
+        let first = if self.chunks[self.processed].is_skipped() {
+            broken.end
+        } else {
+            broken.start + 1
+        };
+
+        if let Some(end) = self.chunk_end(broken.end) {
+            for nonce in nonces.clone() {
+                if let Some(plain) = self.try_decrypt(decipher, sender, broken.end..end, nonce) {
+                    return Resync::Found(broken.end..end, nonce, plain);
+                }
+            }
+        }
+
+        let from = self.resync.unwrap_or(first);
+        for position in from..(first + Self::RESYNC_WINDOW) {
+            if position + 2 > self.data.len() {
+                self.resync = Some(position);
+                return Resync::NeedMoreData;
+            }
+            let length = (&self.data[position..(position + 2)]).get_u16() as usize;
+            // the chunk cannot be shorter than its MAC
+            if length < 16 {
+                continue;
+            }
+            let end = position + 2 + length;
+            if end > self.data.len() {
+                self.resync = Some(position);
+                return Resync::NeedMoreData;
+            }
+            for nonce in nonces.clone() {
+                if let Some(plain) = self.try_decrypt(decipher, sender, position..end, nonce) {
+                    return Resync::Found(position..end, nonce, plain);
+                }
+            }
+        }
+        Resync::NotFound
+    }
+
+    // the end of the chunk that starts at the position, if the chunk is available
+    fn chunk_end(&self, position: usize) -> Option<usize> {
+        if position + 2 <= self.data.len() {
+            let length = (&self.data[position..(position + 2)]).get_u16() as usize;
+            let end = position + 2 + length;
+            if length >= 16 && end <= self.data.len() {
+                return Some(end);
+            }
+        }
+        None
+    }
+
     pub fn decrypted(&self) -> usize {
         self.processed
     }
@@ -91,18 +333,202 @@ impl DirectBuffer {
         self.data.as_ref()
     }
 
-    fn data_mut(&mut self) -> &mut [u8] {
-        self.data.as_mut()
-    }
-
     pub fn chunks(&self) -> &[ChunkInfo] {
         self.chunks.as_ref()
     }
 
+    pub fn has_packet(&self, index: u64) -> bool {
+        self.packets.contains_key(&index)
+    }
+
     pub fn packet(&self, index: u64) -> Range<usize> {
         self.packets
             .get(&index)
             .expect("expected already seen packet")
+            .0
             .clone()
     }
+
+    /// The time of the frame that carries the byte at the position.
+    pub fn time(&self, position: usize) -> Option<Duration> {
+        self.packets
+            .values()
+            .find(|&&(ref range, _)| range.contains(&position))
+            .map(|&(_, timestamp)| timestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::DirectBuffer;
+    use crate::{
+        Decipher, NonceAddition,
+        conversation::Sender,
+        simulator::{test_identity, connection_chunks},
+    };
+
+    // the time of the frames does not matter for the reassembly
+    const T: Duration = Duration::from_secs(0);
+
+    #[test]
+    fn reassembly() {
+        // two chunks, 4 and 3 bytes of body
+        let data = [0, 4, 1, 2, 3, 4, 0, 3, 5, 6, 7];
+        let mut buffer = DirectBuffer::new();
+
+        assert!(buffer.consume(&data[0..4], Some(1000), 1, T));
+        // out of order
+        assert!(buffer.consume(&data[8..], Some(1008), 2, T));
+        assert_eq!(buffer.gap(2), Some(4..8));
+        assert_eq!(buffer.data(), &data[0..4]);
+        // retransmission
+        assert!(!buffer.consume(&data[0..4], Some(1000), 3, T));
+        assert!(!buffer.has_packet(3));
+        // overlaps with the first segment and fills the gap
+        assert!(buffer.consume(&data[2..8], Some(1002), 4, T));
+        assert_eq!(buffer.gap(2), None);
+        assert_eq!(buffer.data(), data.as_ref());
+        assert_eq!(buffer.packet(4), 2..8);
+
+        let chunks = buffer.chunks().iter().map(|c| c.range()).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![0..6, 6..11]);
+    }
+
+    #[test]
+    fn lost_segment() {
+        // chunks of 1000 bytes of body, sent in segments of 1000 bytes
+        let data = (0..81)
+            .flat_map(|_| {
+                let mut chunk = vec![0x11; 1002];
+                chunk[..2].clone_from_slice(1000u16.to_be_bytes().as_ref());
+                chunk
+            })
+            .take(81000)
+            .collect::<Vec<_>>();
+        let mut buffer = DirectBuffer::new();
+
+        // the fourth segment never arrives
+        for i in (0..69).filter(|&i| i != 3) {
+            let segment = &data[(i * 1000)..((i + 1) * 1000)];
+            assert!(buffer.consume(segment, Some(1000 + i as u32 * 1000), i as u64, T));
+        }
+        assert_eq!(buffer.gap(4), Some(3000..4000));
+        assert_eq!(buffer.data(), &data[..3000]);
+
+        // too much data is waiting, the segment is lost
+        for i in 69..81 {
+            let segment = &data[(i * 1000)..((i + 1) * 1000)];
+            assert!(buffer.consume(segment, Some(1000 + i as u32 * 1000), i as u64, T));
+        }
+        assert!(buffer.pending.is_empty());
+        assert_eq!(buffer.gap(4), Some(3000..4000));
+        assert_eq!(buffer.gap(5), None);
+        assert_eq!(buffer.data().len(), data.len());
+        assert_eq!(&buffer.data()[4000..], &data[4000..]);
+
+        let chunks = buffer.chunks().iter().map(|c| c.range()).collect::<Vec<_>>();
+        assert_eq!(chunks[..3], [0..1002, 1002..2004, 2004..4000]);
+        assert!(buffer.chunks()[2].is_skipped());
+    }
+
+    #[test]
+    fn acknowledged_lost_segment() {
+        // two chunks, 4 and 3 bytes of body
+        let data = [0, 4, 1, 2, 3, 4, 0, 3, 5, 6, 7];
+        let mut buffer = DirectBuffer::new();
+
+        let (first, third) = (Duration::from_millis(1), Duration::from_millis(3));
+        assert!(buffer.consume(&data[0..4], Some(1000), 1, first));
+        // the second segment is not captured
+        assert!(buffer.consume(&data[8..], Some(1008), 3, third));
+        assert_eq!(buffer.data(), &data[0..4]);
+        // the peer has not received the second segment yet
+        buffer.acknowledged(1004);
+        assert_eq!(buffer.data(), &data[0..4]);
+        // the peer has received everything, the second segment is lost
+        buffer.acknowledged(1011);
+        assert!(buffer.pending.is_empty());
+        assert_eq!(buffer.gap(3), Some(4..8));
+        assert_eq!(&buffer.data()[8..], &data[8..]);
+        assert_eq!(buffer.time(2), Some(first));
+        assert_eq!(buffer.time(6), None);
+        assert_eq!(buffer.time(9), Some(third));
+    }
+
+    // the connection message of the identity and the decipher of the conversation
+    fn conversation() -> (Vec<u8>, Decipher) {
+        let (identity, _) = test_identity();
+        let (chunk_a, chunk_b, _) = connection_chunks(&identity);
+        let decipher = identity
+            .decipher(chunk_a.raw(), chunk_b.raw())
+            .ok()
+            .unwrap();
+        (chunk_a.raw().clone(), decipher)
+    }
+
+    #[test]
+    fn resync() {
+        let (mut data, decipher) = conversation();
+        let mut broken = 0;
+        for i in 0..4 {
+            let plain = [i as u8; 20];
+            let mut encrypted = decipher
+                .encrypt(plain.as_ref(), NonceAddition::Initiator(i))
+                .unwrap();
+            if i == 1 {
+                // corrupt the body of the second encrypted chunk
+                encrypted[3] ^= 0xff;
+                broken = data.len();
+            }
+            data.extend_from_slice((encrypted.len() as u16).to_be_bytes().as_ref());
+            data.append(&mut encrypted);
+        }
+
+        let mut buffer = DirectBuffer::new();
+        assert!(buffer.consume(&data, None, 1, T));
+        buffer.decrypt(&decipher, Sender::Initiator).unwrap();
+        assert_eq!(buffer.decrypted(), 5);
+        assert!(buffer.chunks()[2].is_skipped());
+        assert_eq!(buffer.chunks()[2].range(), broken..(broken + 2 + 36));
+        assert_eq!(&buffer.data()[(buffer.chunks()[4].range().start + 2)..][..20], &[3; 20]);
+    }
+
+    #[test]
+    fn resync_after_lost_segment() {
+        let (mut data, decipher) = conversation();
+        let mut starts = vec![];
+        // enough chunks to consider the missing segment lost
+        for i in 0..0x800 {
+            let plain = [i as u8; 20];
+            let mut encrypted = decipher
+                .encrypt(plain.as_ref(), NonceAddition::Initiator(i))
+                .unwrap();
+            starts.push(data.len());
+            data.extend_from_slice((encrypted.len() as u16).to_be_bytes().as_ref());
+            data.append(&mut encrypted);
+        }
+
+        // the segment from the middle of the third encrypted chunk
+        // to the middle of the fourth is lost
+        let lost = (starts[2] + 10)..(starts[3] + 20);
+        let mut buffer = DirectBuffer::new();
+        assert!(buffer.consume(&data[..lost.start], Some(0), 1, T));
+        buffer.decrypt(&decipher, Sender::Initiator).unwrap();
+        assert_eq!(buffer.decrypted(), 3);
+        for (i, segment) in data[lost.end..].chunks(1000).enumerate() {
+            let seq = (lost.end + i * 1000) as u32;
+            assert!(buffer.consume(segment, Some(seq), 2 + i as u64, T));
+            buffer.decrypt(&decipher, Sender::Initiator).unwrap();
+        }
+        assert_eq!(buffer.gap(2), Some(lost.clone()));
+
+        // the chunks from the start of the third to the start of the fifth are skipped
+        assert!(buffer.chunks()[3].is_skipped());
+        assert_eq!(buffer.chunks()[3].range(), starts[2]..starts[4]);
+        assert_eq!(buffer.chunks()[4].range().start, starts[4]);
+        assert_eq!(buffer.decrypted(), buffer.chunks().len());
+        let last = buffer.chunks().last().unwrap().range();
+        assert_eq!(&buffer.data()[(last.start + 2)..][..20], &[0xff; 20]);
+    }
 }
@@ -0,0 +1,183 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{collections::{BTreeMap, HashMap}, ops::Range, rc::Rc};
+use super::addresses::Sender;
+use crate::value::{Decoded, Protocol};
+
+/// The message decoded in the coordinates of the whole direct buffer,
+/// so it can be rendered on any frame it intersects.
+pub struct DecodedMessage {
+    pub decoded: Decoded,
+    // the names of the messages for the summary of the frame
+    pub names: Vec<String>,
+    // the error and the range where the decoding failed
+    pub error: Option<(String, Range<usize>)>,
+    // the position where the next message starts, unknown if the length of the message
+    // runs past the data received so far, the message is either unfinished or malformed
+    pub next: Option<(usize, usize)>,
+}
+
+impl DecodedMessage {
+    fn size(&self) -> usize {
+        let error = self.error.as_ref().map(|&(ref e, _)| e.len()).unwrap_or(0);
+        let names = self.names.iter().map(String::capacity).sum::<usize>();
+        self.decoded.size() + error + names
+    }
+}
+
+// the conversation, the direction and the first chunk of the message
+type Key = (usize, Sender, usize);
+
+/// The messages decoded once the first pass is complete, keyed by the conversation
+/// and their first chunk, all conversations share the cache.
+/// Wireshark calls the dissector again on every click and every filter pass,
+/// the cache prevents decoding the same message again and again.
+/// When the approximate size of the messages exceeds the budget,
+/// the least recently used ones are dropped.
+pub struct MessageCache {
+    protocol: Option<Protocol>,
+    messages: HashMap<Key, (Rc<DecodedMessage>, u64)>,
+    // the key of the message by the time it is used
+    usage: BTreeMap<u64, Key>,
+    time: u64,
+    size: usize,
+    budget: usize,
+}
+
+impl MessageCache {
+    // the approximate size of the decoded messages of all conversations
+    const BUDGET: usize = 0x4000000;
+
+    pub fn new() -> Self {
+        Self::with_budget(Self::BUDGET)
+    }
+
+    fn with_budget(budget: usize) -> Self {
+        MessageCache {
+            protocol: None,
+            messages: HashMap::new(),
+            usage: BTreeMap::new(),
+            time: 0,
+            size: 0,
+            budget,
+        }
+    }
+
+    /// The messages are decoded with the encoding of the protocol,
+    /// the cache is dropped if the protocol changes.
+    pub fn set_protocol(&mut self, protocol: Option<Protocol>) {
+        if self.protocol != protocol {
+            self.protocol = protocol;
+            self.clear();
+        }
+    }
+
+    /// Drop all messages, the conversations they belong are gone.
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.usage.clear();
+        self.size = 0;
+    }
+
+    pub fn get(
+        &mut self,
+        conversation: usize,
+        sender: Sender,
+        chunk: usize,
+    ) -> Option<Rc<DecodedMessage>> {
+        let time = self.time;
+        let key = (conversation, sender, chunk);
+        let &mut (ref message, ref mut used) = self.messages.get_mut(&key)?;
+        self.usage.remove(used);
+        self.usage.insert(time, key);
+        *used = time;
+        self.time += 1;
+        Some(message.clone())
+    }
+
+    pub fn insert(
+        &mut self,
+        conversation: usize,
+        sender: Sender,
+        chunk: usize,
+        message: Rc<DecodedMessage>,
+    ) {
+        let size = message.size();
+        let key = (conversation, sender, chunk);
+        // the replaced message frees its space before anything is evicted
+        if let Some((old, used)) = self.messages.remove(&key) {
+            self.usage.remove(&used);
+            self.size -= old.size();
+        }
+        if size > self.budget {
+            return;
+        }
+        while self.size + size > self.budget {
+            let (&time, _) = self.usage.iter().next().expect("the cache is not empty");
+            let key = self.usage.remove(&time).unwrap();
+            let (evicted, _) = self.messages.remove(&key).unwrap();
+            self.size -= evicted.size();
+        }
+        self.messages.insert(key, (message, self.time));
+        self.usage.insert(self.time, key);
+        self.time += 1;
+        self.size += size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use wireshark_definitions::TreeLeaf;
+    use super::{DecodedMessage, MessageCache, Sender};
+    use crate::value::{Decoded, Protocol};
+
+    fn message() -> Rc<DecodedMessage> {
+        let mut decoded = Decoded::root();
+        decoded.add("message", 0..0, TreeLeaf::Display("0".repeat(0x100)));
+        Rc::new(DecodedMessage {
+            decoded,
+            names: Vec::new(),
+            error: None,
+            next: Some((1, 0)),
+        })
+    }
+
+    #[test]
+    fn least_recently_used_evicted() {
+        let size = message().size();
+        let mut cache = MessageCache::with_budget(size * 2);
+        cache.insert(0, Sender::Initiator, 0, message());
+        cache.insert(0, Sender::Responder, 0, message());
+        assert!(cache.get(0, Sender::Initiator, 0).is_some());
+        cache.insert(0, Sender::Initiator, 1, message());
+        assert!(cache.get(0, Sender::Responder, 0).is_none());
+        assert!(cache.get(0, Sender::Initiator, 0).is_some());
+        assert!(cache.get(0, Sender::Initiator, 1).is_some());
+
+        // the messages decoded with other protocol are dropped
+        cache.set_protocol(Some(Protocol::Proto007));
+        assert!(cache.get(0, Sender::Initiator, 0).is_none());
+    }
+
+    #[test]
+    fn shared_between_conversations() {
+        let size = message().size();
+        let mut cache = MessageCache::with_budget(size * 2);
+        cache.insert(0, Sender::Initiator, 0, message());
+        cache.insert(1, Sender::Initiator, 0, message());
+        assert!(cache.get(0, Sender::Initiator, 0).is_some());
+        assert!(cache.get(1, Sender::Initiator, 0).is_some());
+
+        // the replaced message does not evict anything
+        cache.insert(1, Sender::Initiator, 0, message());
+        assert!(cache.get(0, Sender::Initiator, 0).is_some());
+        assert!(cache.get(1, Sender::Initiator, 0).is_some());
+
+        // the budget is shared, the least recently used conversation is evicted
+        cache.insert(2, Sender::Initiator, 0, message());
+        assert!(cache.get(0, Sender::Initiator, 0).is_none());
+        assert!(cache.get(1, Sender::Initiator, 0).is_some());
+    }
+}
@@ -20,6 +20,12 @@ mod direct_buffer;
 /// both incoming and outgoing buffer and also addresses
 mod overall_buffer;
 
+/// the decoded messages, rendered again without decoding
+mod message_cache;
+
+/// the short description of the frame, the names of the messages in it
+mod summary;
+
 /// the whole conversation information, so called conversation context
 // TODO: refactor, simplify
 mod context;
@@ -27,4 +33,7 @@ mod context;
 mod context_wrapper;
 
 pub use self::addresses::Sender;
+pub use self::chunk_info::ChunkInfo;
 pub use self::context_wrapper::Context;
+pub use self::message_cache::MessageCache;
+pub use self::summary::{Summary, MessageRecord};
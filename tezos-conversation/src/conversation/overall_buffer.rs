@@ -9,7 +9,7 @@ use super::{
 };
 use crate::{
     identity::Decipher,
-    proof_of_work::check_proof_of_work,
+    proof_of_work::{check_proof_of_work, proof_of_work_difficulty},
 };
 
 pub struct ConversationBuffer {
@@ -40,10 +40,20 @@ impl ConversationBuffer {
     where
         P: PacketMetadata,
     {
+        // the segment acknowledges the data sent in the opposite direction
+        if let Some(ack) = packet_info.ack() {
+            match self.sender(packet_info) {
+                Sender::Initiator => self.outgoing.acknowledged(ack),
+                Sender::Responder => self.incoming.acknowledged(ack),
+            }
+        }
         let target = self.pow_target;
         let direct_buffer = self.direct_buffer_mut(packet_info);
         let already_checked = direct_buffer.data().len() >= Self::CHECK_RANGE.end;
-        direct_buffer.consume(payload, packet_info.frame_number());
+        let (seq, frame_number) = (packet_info.seq(), packet_info.frame_number());
+        if !direct_buffer.consume(payload, seq, frame_number, packet_info.timestamp()) {
+            log::info!("retransmission dropped, frame: {}", frame_number);
+        }
         let data = direct_buffer.data();
         // if after consume have enough bytes, let's check the proof of work
         let can_check = data.len() >= Self::CHECK_RANGE.end;
@@ -54,6 +64,23 @@ impl ConversationBuffer {
         }
     }
 
+    /// The difficulty of the proof of work of the sender of the packet,
+    /// and the range of the stamp.
+    pub fn proof_of_work_difficulty<P>(&self, packet_info: &P) -> Option<(f64, Range<usize>)>
+    where
+        P: PacketMetadata,
+    {
+        let data = self.direct_buffer(packet_info).data();
+        if data.len() >= Self::CHECK_RANGE.end {
+            let difficulty = proof_of_work_difficulty(&data[Self::CHECK_RANGE]);
+            // 32 bytes public key goes before the stamp
+            let stamp_range = (Self::CHECK_RANGE.start + 32)..Self::CHECK_RANGE.end;
+            Some((difficulty, stamp_range))
+        } else {
+            None
+        }
+    }
+
     pub fn id(&self) -> String {
         format!("{}", self.addresses)
     }
@@ -0,0 +1,102 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{fmt, time::Duration};
+
+/// The short description of the frame for the packet list,
+/// the names of the messages in the order they appear in the frame,
+/// and the notes about the frame, like buffering or errors.
+/// Also the records of the messages that end in the frame, for the statistics.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    // the name and how many times it repeats in a row
+    items: Vec<(String, usize)>,
+    records: Vec<MessageRecord>,
+}
+
+/// The message for the statistics, each message is recorded once,
+/// in the frame where it ends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageRecord {
+    pub name: String,
+    // the role of the sender, the same as the source field shows
+    pub direction: &'static str,
+    pub conversation: String,
+    // the number of bytes of the message, without the chunk headers and MACs
+    pub size: usize,
+    // from the frame where the message starts to the frame where it ends
+    pub duration: Duration,
+}
+
+impl Summary {
+    pub fn new() -> Self {
+        Summary::default()
+    }
+
+    /// The message, the same message repeated in a row is counted rather than repeated.
+    pub fn message<S>(&mut self, name: S)
+    where
+        S: AsRef<str>,
+    {
+        match self.items.last_mut() {
+            Some(&mut (ref last, ref mut count)) if last == name.as_ref() => *count += 1,
+            _ => self.items.push((name.as_ref().to_owned(), 1)),
+        }
+    }
+
+    /// The note is shown in brackets once per frame.
+    pub fn note(&mut self, note: &str) {
+        let note = format!("[{}]", note);
+        if !self.items.iter().any(|&(ref item, _)| item == &note) {
+            self.items.push((note, 1));
+        }
+    }
+
+    pub fn record(&mut self, record: MessageRecord) {
+        self.records.push(record);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn records(&self) -> &[MessageRecord] {
+        &self.records
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, &(ref item, count)) in self.items.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            if count > 1 {
+                write!(f, "{} \u{d7}{}", item, count)?;
+            } else {
+                write!(f, "{}", item)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Summary;
+
+    #[test]
+    fn repeated_messages_counted() {
+        let mut summary = Summary::new();
+        summary.message("GetCurrentBranch");
+        summary.message("BlockHeader");
+        summary.message("BlockHeader");
+        summary.message("BlockHeader");
+        summary.note("buffering");
+        summary.note("buffering");
+        assert_eq!(
+            summary.to_string(),
+            "GetCurrentBranch, BlockHeader \u{d7}3, [buffering]"
+        );
+    }
+}
@@ -5,13 +5,22 @@ use serde::Deserialize;
 use crypto::{
     crypto_box::{PrecomputedKey, precompute, decrypt, encrypt, CryptoError},
     nonce::{NoncePair, Nonce, generate_nonces},
+    hash::HashType,
+    blake2b,
 };
 use tezos_messages::p2p::encoding::{
     connection::ConnectionMessage,
     version::NetworkVersion,
 };
-use std::{path::Path, ops::Add};
+use std::{path::Path, ops::Add, convert::TryFrom};
 use num_bigint::BigUint;
+use sodiumoxide::crypto::box_;
+use failure::Fail;
+use crate::{
+    key_log::key_log_line,
+    proof_of_work::check_proof_of_work_detached,
+    conversation::Sender,
+};
 
 #[derive(Deserialize, Clone, Debug, PartialEq)]
 /// Node identity information
@@ -20,13 +29,42 @@ pub struct Identity {
     public_key: String,
     secret_key: String,
     proof_of_work_stamp: String,
+    // filled by `Identity::checked`, not present in the file
+    #[serde(skip)]
+    inconsistencies: Vec<IdentityInconsistency>,
 }
 
 pub enum IdentityError {
     Invalid,
+    Inconsistent,
     CannotDecrypt,
 }
 
+/// The problem found in the identity file.
+#[derive(Debug, Clone, PartialEq, Fail)]
+pub enum IdentityInconsistency {
+    #[fail(display = "{} is not a hex string of {} bytes", field, size)]
+    BadHex { field: &'static str, size: usize },
+    #[fail(display = "public_key does not match secret_key")]
+    KeyPairMismatch,
+    #[fail(display = "peer_id: {} does not match public_key, expected: {}", actual, expected)]
+    PeerIdMismatch { expected: String, actual: String },
+    #[fail(display = "proof_of_work_stamp does not satisfy target: {}", _0)]
+    ProofOfWork(f64),
+}
+
+impl IdentityInconsistency {
+    /// The identity cannot decrypt anything if it has such inconsistency.
+    pub fn fatal(&self) -> bool {
+        match self {
+            &IdentityInconsistency::BadHex { .. } => true,
+            &IdentityInconsistency::KeyPairMismatch => true,
+            &IdentityInconsistency::PeerIdMismatch { .. } => false,
+            &IdentityInconsistency::ProofOfWork(_) => false,
+        }
+    }
+}
+
 impl Identity {
     /// Read and deserialize the identity from json file using serde.
     pub fn from_path<P>(path: P) -> Result<Self, failure::Error>
@@ -37,6 +75,69 @@ impl Identity {
         serde_json::from_str(&content).map_err(Into::into)
     }
 
+    /// Check the identity and remember found inconsistencies.
+    pub fn checked(self, pow_target: f64) -> Self {
+        let mut s = self;
+        s.inconsistencies = s.diagnose(pow_target);
+        s
+    }
+
+    /// Inconsistencies found by `Identity::checked`.
+    pub fn inconsistencies(&self) -> &[IdentityInconsistency] {
+        self.inconsistencies.as_ref()
+    }
+
+    /// Check if the keys form a pair, if the peer id is the hash of the public key
+    /// and if the proof of work stamp satisfies the target.
+    pub fn diagnose(&self, pow_target: f64) -> Vec<IdentityInconsistency> {
+        fn decode<'a, A>(
+            hex_string: &str,
+            field: &'static str,
+            size: usize,
+            inconsistencies: &mut Vec<IdentityInconsistency>,
+        ) -> Option<A>
+        where
+            A: for<'b> TryFrom<&'b [u8]>,
+        {
+            let value = hex::decode(hex_string)
+                .ok()
+                .and_then(|bytes| A::try_from(bytes.as_slice()).ok());
+            if value.is_none() {
+                inconsistencies.push(IdentityInconsistency::BadHex { field, size });
+            }
+            value
+        }
+
+        let mut inconsistencies = Vec::new();
+        let v = &mut inconsistencies;
+        let pk = decode::<[u8; 0x20]>(&self.public_key, "public_key", 0x20, v);
+        let sk = decode::<[u8; 0x20]>(&self.secret_key, "secret_key", 0x20, v);
+        let pow = decode::<[u8; 0x18]>(&self.proof_of_work_stamp, "proof_of_work_stamp", 0x18, v);
+
+        if let (&Some(ref pk), &Some(ref sk)) = (&pk, &sk) {
+            if box_::SecretKey(sk.clone()).public_key() != box_::PublicKey(pk.clone()) {
+                inconsistencies.push(IdentityInconsistency::KeyPairMismatch);
+            }
+        }
+        if let &Some(ref pk) = &pk {
+            let hash = blake2b::digest_128(pk.as_ref());
+            let expected = HashType::CryptoboxPublicKeyHash.bytes_to_string(hash.as_ref());
+            if expected != self.peer_id {
+                inconsistencies.push(IdentityInconsistency::PeerIdMismatch {
+                    expected,
+                    actual: self.peer_id.clone(),
+                });
+            }
+            if let &Some(ref pow) = &pow {
+                if check_proof_of_work_detached(pk, pow, pow_target).is_err() {
+                    inconsistencies.push(IdentityInconsistency::ProofOfWork(pow_target));
+                }
+            }
+        }
+
+        inconsistencies
+    }
+
     pub fn connection_message(&self) -> ConnectionMessage {
         let version = NetworkVersion::new("testnet".to_owned(), 0, 0);
         ConnectionMessage::new(1234, &self.public_key, &self.proof_of_work_stamp, [0; 24].as_ref(), vec![version])
@@ -50,29 +151,68 @@ impl Identity {
     ) -> Result<Decipher, IdentityError> {
         let initiator_pk_string = hex::encode(&initiator_chunk[4..36]);
         let responder_pk_string = hex::encode(&responder_chunk[4..36]);
-        // check if the identity belong to one of the parties
+        // check if the identity belong to one of the parties,
+        // our node might either initiate the connection or accept it
+        let (other_pk, local) = if initiator_pk_string == self.public_key {
+            (responder_pk_string, Sender::Initiator)
+        } else if responder_pk_string == self.public_key {
+            (initiator_pk_string, Sender::Responder)
+        } else {
+            return Err(IdentityError::CannotDecrypt);
+        };
+        if self.inconsistencies.iter().any(IdentityInconsistency::fatal) {
+            return Err(IdentityError::Inconsistent);
+        }
+
+        let key = precompute(&other_pk, &self.secret_key).map_err(|_| IdentityError::Invalid)?;
+        Ok(Decipher::new(key, initiator_chunk, responder_chunk, Some(local)))
+    }
+
+    /// Create a line of the key log file for the conversation using connection message pair.
+    /// The line allows to decrypt the conversation without sharing the secret key.
+    pub fn key_log_line(
+        &self,
+        initiator_chunk: &[u8],
+        responder_chunk: &[u8],
+    ) -> Result<String, IdentityError> {
+        let initiator_pk_string = hex::encode(&initiator_chunk[4..36]);
+        let responder_pk_string = hex::encode(&responder_chunk[4..36]);
         let other_pk = if initiator_pk_string == self.public_key {
-            responder_pk_string
+            &responder_chunk[4..36]
         } else if responder_pk_string == self.public_key {
-            initiator_pk_string
+            &initiator_chunk[4..36]
         } else {
             return Err(IdentityError::CannotDecrypt);
         };
 
-        Ok(Decipher {
-            key: precompute(&other_pk, &self.secret_key)
-                .map_err(|_| IdentityError::Invalid)?,
-            // initiator/responder is not the same as local/remote party,
-            // but let's only in this module treat initiator as local party, and responder as remote
-            nonce: generate_nonces(initiator_chunk, responder_chunk, false),
-        })
+        let other_pk = box_::PublicKey::from_slice(other_pk).ok_or(IdentityError::Invalid)?;
+        let sk = hex::decode(&self.secret_key)
+            .ok()
+            .and_then(|sk| box_::SecretKey::from_slice(&sk))
+            .ok_or(IdentityError::Invalid)?;
+        let box_::PrecomputedKey(key) = box_::precompute(&other_pk, &sk);
+        let (initiator_nonce, responder_nonce) = initial_nonces(initiator_chunk, responder_chunk);
+        Ok(key_log_line(&key, &initiator_nonce, &responder_nonce))
     }
 }
 
+/// The nonces the initiator and the responder encrypt their first chunks with.
+/// They depend only on the connection messages, not on which party is our node.
+pub(crate) fn initial_nonces(initiator_chunk: &[u8], responder_chunk: &[u8]) -> (Nonce, Nonce) {
+    // `generate_nonces` takes the point of view of some node, from the point of view of
+    // the initiator the first message is sent, not incoming,
+    // so its `local` nonce is the initiator nonce and `remote` is the responder nonce
+    let NoncePair { local, remote } = generate_nonces(initiator_chunk, responder_chunk, false);
+    (local, remote)
+}
+
 /// Decipher object, contains precomputed key and initial nonces
 pub struct Decipher {
     key: PrecomputedKey,
-    nonce: NoncePair,
+    initiator_nonce: Nonce,
+    responder_nonce: Nonce,
+    // the party whose identity created the decipher, unknown if the key is from the key log
+    local: Option<Sender>,
 }
 
 /// Identification of the chunk, its number and direction
@@ -92,22 +232,116 @@ fn add_nonce(nonce: &Nonce, addition: u64) -> Nonce {
 
 
 impl Decipher {
-    pub fn decrypt(&self, enc: &[u8], chunk_number: NonceAddition) -> Result<Vec<u8>, CryptoError> {
-        // prepare the actual nonce for the message
-        let nonce = match chunk_number {
-            NonceAddition::Initiator(addition) => add_nonce(&self.nonce.local, addition),
-            NonceAddition::Responder(addition) => add_nonce(&self.nonce.remote, addition),
-        };
+    pub(crate) fn new(
+        key: PrecomputedKey,
+        initiator_chunk: &[u8],
+        responder_chunk: &[u8],
+        local: Option<Sender>,
+    ) -> Self {
+        let (initiator_nonce, responder_nonce) = initial_nonces(initiator_chunk, responder_chunk);
+        Decipher {
+            key,
+            initiator_nonce,
+            responder_nonce,
+            local,
+        }
+    }
 
-        decrypt(enc, &nonce, &self.key)
+    /// The party of the conversation our node is, if known.
+    pub(crate) fn local(&self) -> Option<&Sender> {
+        self.local.as_ref()
+    }
+
+    // prepare the actual nonce for the message,
+    // the key is the same in both directions, only the nonce differs
+    fn nonce(&self, chunk_number: NonceAddition) -> Nonce {
+        match chunk_number {
+            NonceAddition::Initiator(addition) => add_nonce(&self.initiator_nonce, addition),
+            NonceAddition::Responder(addition) => add_nonce(&self.responder_nonce, addition),
+        }
+    }
+
+    pub fn decrypt(&self, enc: &[u8], chunk_number: NonceAddition) -> Result<Vec<u8>, CryptoError> {
+        decrypt(enc, &self.nonce(chunk_number), &self.key)
     }
 
     pub fn encrypt(&self, msg: &[u8], chunk_number: NonceAddition) -> Result<Vec<u8>, CryptoError> {
-        let nonce = match chunk_number {
-            NonceAddition::Initiator(addition) => add_nonce(&self.nonce.local, addition),
-            NonceAddition::Responder(addition) => add_nonce(&self.nonce.remote, addition),
-        };
+        encrypt(msg, &self.nonce(chunk_number), &self.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crypto::{crypto_box::{precompute, encrypt}, nonce::generate_nonces};
+    use super::{Identity, IdentityInconsistency, IdentityError, NonceAddition};
+    use crate::{
+        proof_of_work::DEFAULT_TARGET,
+        conversation::Sender,
+        simulator::{test_identity, connection_chunks},
+    };
+
+    fn identity() -> Identity {
+        test_identity().0
+    }
+
+    #[test]
+    fn consistent() {
+        assert_eq!(identity().diagnose(DEFAULT_TARGET), vec![]);
+    }
+
+    #[test]
+    fn inconsistent() {
+        let mut identity = identity();
+        identity.secret_key = identity.secret_key.replace('8', "9");
+        identity.peer_id = "idtqxHUjbjbCfaDn4jczoPGsnhacKX".to_owned();
+        let inconsistencies = identity.diagnose(32.0);
+        assert_eq!(inconsistencies[0], IdentityInconsistency::KeyPairMismatch);
+        match &inconsistencies[1] {
+            &IdentityInconsistency::PeerIdMismatch { ref expected, .. } => {
+                assert_eq!(expected, "idssJHDL1z8fkryZaYVF9fQRMktoWg")
+            },
+            _ => panic!(),
+        }
+        assert_eq!(inconsistencies[2], IdentityInconsistency::ProofOfWork(32.0));
+
+        let mut chunk = [0; 60];
+        chunk[4..36].clone_from_slice(&hex::decode(&identity.public_key).unwrap());
+        match identity.checked(DEFAULT_TARGET).decipher(&chunk, &[0; 60]) {
+            Err(IdentityError::Inconsistent) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn responder() {
+        let identity = identity();
+        let (chunk_local, chunk_remote, sk) = connection_chunks(&identity);
+
+        // the remote node initiates the connection to our node
+        let decipher = identity
+            .decipher(chunk_remote.raw(), chunk_local.raw())
+            .ok()
+            .unwrap();
+        assert_eq!(decipher.local(), Some(&Sender::Responder));
+        let plain = b"some message".as_ref();
+
+        // the remote node encrypts as the initiator, its connection message is not incoming
+        let key = precompute(&identity.public_key, &hex::encode(sk.as_ref())).unwrap();
+        let nonce = generate_nonces(chunk_remote.raw(), chunk_local.raw(), false);
+        let encrypted = encrypt(plain, &nonce.local, &key).unwrap();
+        let decrypted = decipher
+            .decrypt(&encrypted, NonceAddition::Initiator(0))
+            .unwrap();
+        assert_eq!(decrypted.as_slice(), plain);
 
-        encrypt(msg, &nonce, &self.key)
+        // our node answers, for it the connection is incoming
+        let remote_pk = hex::encode(&chunk_remote.raw()[4..36]);
+        let key = precompute(&remote_pk, &identity.secret_key).unwrap();
+        let nonce = generate_nonces(chunk_local.raw(), chunk_remote.raw(), true);
+        let encrypted = encrypt(plain, &nonce.local, &key).unwrap();
+        let decrypted = decipher
+            .decrypt(&encrypted, NonceAddition::Responder(0))
+            .unwrap();
+        assert_eq!(decrypted.as_slice(), plain);
     }
 }
@@ -0,0 +1,182 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use crypto::{
+    crypto_box::PrecomputedKey,
+    nonce::{NoncePair, Nonce},
+};
+use failure::Fail;
+use std::{path::Path, convert::TryFrom};
+use crate::identity::{Decipher, IdentityError, initial_nonces};
+
+/// The label that starts each line of the key log file.
+pub const KEY_LOG_LABEL: &str = "TEZOS_CONVERSATION";
+
+/// The analogue of the TLS `SSLKEYLOGFILE`. Each line describes a single conversation:
+/// `TEZOS_CONVERSATION <precomputed key> <initiator nonce> <responder nonce>`,
+/// all values are hex encoded. The nonces are derived from the pair of connection messages,
+/// so they identify the conversation. Empty lines and lines starting with `#` are ignored.
+#[derive(PartialEq)]
+pub struct KeyLog {
+    entries: Vec<KeyLogEntry>,
+}
+
+#[derive(PartialEq)]
+struct KeyLogEntry {
+    key: [u8; 0x20],
+    initiator_nonce: [u8; 0x18],
+    responder_nonce: [u8; 0x18],
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "Key log line {}: {}", line, reason)]
+pub struct KeyLogError {
+    line: usize,
+    reason: &'static str,
+}
+
+impl KeyLog {
+    /// Read and parse the key log file.
+    pub fn from_path<P>(path: P) -> Result<Self, failure::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let content = std::fs::read_to_string(path.as_ref())?;
+        content.parse::<Self>().map_err(Into::into)
+    }
+
+    /// Create a decipher object using connection message pair,
+    /// if the key log has the entry for this conversation.
+    pub fn decipher(
+        &self,
+        initiator_chunk: &[u8],
+        responder_chunk: &[u8],
+    ) -> Result<Decipher, IdentityError> {
+        let (initiator_nonce, responder_nonce) = initial_nonces(initiator_chunk, responder_chunk);
+        let initiator_nonce = initiator_nonce.get_bytes();
+        let responder_nonce = responder_nonce.get_bytes();
+        self.entries
+            .iter()
+            .find(|e| {
+                e.initiator_nonce.as_ref() == initiator_nonce.as_slice()
+                    && e.responder_nonce.as_ref() == responder_nonce.as_slice()
+            })
+            .map(|e| {
+                let key = PrecomputedKey::from_bytes(e.key);
+                // the key log does not tell which party is our node
+                Decipher::new(key, initiator_chunk, responder_chunk, None)
+            })
+            .ok_or(IdentityError::CannotDecrypt)
+    }
+}
+
+impl std::str::FromStr for KeyLog {
+    type Err = KeyLogError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn field<'a, A>(
+            words: &mut impl Iterator<Item = &'a str>,
+            line: usize,
+            reason: &'static str,
+        ) -> Result<A, KeyLogError>
+        where
+            A: for<'b> TryFrom<&'b [u8]>,
+        {
+            words
+                .next()
+                .and_then(|word| hex::decode(word).ok())
+                .and_then(|bytes| A::try_from(bytes.as_slice()).ok())
+                .ok_or(KeyLogError { line, reason })
+        }
+
+        let mut entries = Vec::new();
+        for (index, line) in s.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            if words.next() != Some(KEY_LOG_LABEL) {
+                return Err(KeyLogError {
+                    line: line_number,
+                    reason: "unknown label",
+                });
+            }
+            entries.push(KeyLogEntry {
+                key: field(&mut words, line_number, "bad precomputed key")?,
+                initiator_nonce: field(&mut words, line_number, "bad initiator nonce")?,
+                responder_nonce: field(&mut words, line_number, "bad responder nonce")?,
+            });
+        }
+
+        Ok(KeyLog { entries })
+    }
+}
+
+/// Format the line of the key log file.
+/// The node should write it for each conversation, `initiator_nonce` is the nonce
+/// the initiator of the connection encrypts its first message with,
+/// and `responder_nonce` is the one the responder uses.
+pub fn key_log_line(key: &[u8; 0x20], initiator_nonce: &Nonce, responder_nonce: &Nonce) -> String {
+    format!(
+        "{} {} {} {}",
+        KEY_LOG_LABEL,
+        hex::encode(key),
+        hex::encode(initiator_nonce.get_bytes()),
+        hex::encode(responder_nonce.get_bytes()),
+    )
+}
+
+/// Same as `key_log_line`, but takes the nonces in the form the node has them.
+pub fn key_log_line_from_pair(key: &[u8; 0x20], nonce: &NoncePair, incoming: bool) -> String {
+    // the nonce the node uses for sending is `local`
+    if incoming {
+        key_log_line(key, &nonce.remote, &nonce.local)
+    } else {
+        key_log_line(key, &nonce.local, &nonce.remote)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyLog;
+    use crate::{NonceAddition, simulator::{test_identity, connection_chunks}};
+
+    #[test]
+    fn decrypt_by_key_log() {
+        let (identity, _) = test_identity();
+        let (chunk_a, chunk_b, _) = connection_chunks(&identity);
+
+        let line = identity
+            .key_log_line(chunk_a.raw(), chunk_b.raw())
+            .ok()
+            .unwrap();
+        let content = format!("# comment\n\n{}\n", line);
+        let key_log = content.parse::<KeyLog>().unwrap();
+
+        let plain = b"some message".as_ref();
+        let encrypted = identity
+            .decipher(chunk_a.raw(), chunk_b.raw())
+            .ok()
+            .unwrap()
+            .encrypt(plain, NonceAddition::Responder(3))
+            .unwrap();
+        let decrypted = key_log
+            .decipher(chunk_a.raw(), chunk_b.raw())
+            .ok()
+            .unwrap()
+            .decrypt(&encrypted, NonceAddition::Responder(3))
+            .unwrap();
+        assert_eq!(decrypted.as_slice(), plain);
+
+        // the key log does not know the swapped conversation
+        assert!(key_log.decipher(chunk_b.raw(), chunk_a.raw()).is_err());
+    }
+
+    #[test]
+    fn bad_line() {
+        assert!("TEZOS_CONVERSATION 00".parse::<KeyLog>().is_err());
+        assert!("SOMETHING_ELSE".parse::<KeyLog>().is_err());
+    }
+}
@@ -17,14 +17,19 @@ mod range_tool;
 
 mod identity;
 
+mod key_log;
+
 pub mod proof_of_work;
 
 mod simulator;
 
-pub use self::identity::{Identity, IdentityError, Decipher, NonceAddition};
-pub use self::conversation::Context;
-pub use self::value::TezosEncoded;
+pub use self::identity::{Identity, IdentityError, IdentityInconsistency, Decipher, NonceAddition};
+pub use self::key_log::{KeyLog, KeyLogError, KEY_LOG_LABEL, key_log_line, key_log_line_from_pair};
+pub use self::conversation::{Context, MessageCache, Summary, MessageRecord};
+pub use self::value::{TezosEncoded, Protocol};
 pub use self::simulator::{
     Tree, PacketDescriptor, ChunkDescriptor,
-    simulate_foreign, simulate_handshake, simulate_encrypted,
+    simulate_foreign, simulate_handshake, simulate_encrypted, simulate_decoding,
 };
+#[cfg(feature = "bench")]
+pub use self::simulator::simulate_estimated_decoding;
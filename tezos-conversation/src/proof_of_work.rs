@@ -60,6 +60,15 @@ pub fn check_proof_of_work_detached(
     check_proof_of_work(data.as_ref(), target)
 }
 
+/// The measured difficulty of the proof of work, the check passes
+/// if the difficulty is greater or equal than the target.
+pub fn proof_of_work_difficulty(data: &[u8]) -> f64 {
+    // the hash is a little endian 256-bit integer, f64 is enough to estimate its logarithm
+    let hash = blake2b::digest_256(data);
+    let hash_number = hash.iter().rev().fold(0.0, |a, b| a * 256.0 + f64::from(*b));
+    256.0 - hash_number.log2()
+}
+
 fn check_proof_of_work_inner(data: &[u8], target_number: &BigUint) -> Result<(), ()> {
     let hash = blake2b::digest_256(data);
     let hash_number = BigUint::from_bytes_le(hash.as_ref());
@@ -90,7 +99,9 @@ fn make_target(target: f64) -> BigUint {
 mod tests {
     use num_bigint::BigUint;
     use std::convert::TryFrom;
-    use super::{generate_proof_of_work, check_proof_of_work, DEFAULT_TARGET};
+    use super::{
+        generate_proof_of_work, check_proof_of_work, proof_of_work_difficulty, DEFAULT_TARGET,
+    };
 
     // `BigUint::from_bytes_le` is the same as `Z.of_bits`
     #[test]
@@ -118,6 +129,10 @@ mod tests {
         )
         .unwrap();
         check_proof_of_work(data.as_ref(), DEFAULT_TARGET).unwrap();
+        let difficulty = proof_of_work_difficulty(data.as_ref());
+        assert!(difficulty >= DEFAULT_TARGET);
+        check_proof_of_work(data.as_ref(), difficulty - 0.01).unwrap();
+        check_proof_of_work(data.as_ref(), difficulty + 0.01).unwrap_err();
     }
 
     #[test]
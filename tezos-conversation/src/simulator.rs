@@ -1,11 +1,20 @@
-use wireshark_definitions::{PacketMetadata, SocketAddress, TreePresenter, TreeLeaf};
+use wireshark_definitions::{
+    PacketMetadata, SocketAddress, TreePresenter, TreeLeaf, TCP_FLAG_ACK, TCP_FLAG_PSH,
+};
 use tezos_messages::p2p::{
     binary_message::{BinaryChunk, BinaryMessage},
     encoding::connection::ConnectionMessage,
 };
+use tezos_encoding::encoding::Encoding;
 use sodiumoxide::crypto::box_;
-use std::{fmt, ops::Range};
-use crate::{Context, Identity, NonceAddition};
+use std::{fmt, ops::Range, time::Duration};
+use crate::{
+    Context, Identity, MessageCache, NonceAddition,
+    conversation::ChunkInfo,
+    value::{ChunkedData, Decoded, show},
+};
+#[cfg(feature = "bench")]
+use crate::value::estimate_size;
 
 #[derive(Default, Clone)]
 pub struct Tree {
@@ -36,6 +45,14 @@ impl TreePresenter for Tree {
         }
         self.clone()
     }
+
+    fn expert<D, P>(&mut self, name: P, message: D)
+    where
+        D: fmt::Display,
+        P: AsRef<str>,
+    {
+        let _ = (name, message);
+    }
 }
 
 struct Packet {
@@ -44,6 +61,8 @@ struct Packet {
     number: u64,
     visited: bool,
     swapped: bool,
+    seq: Option<u32>,
+    flags: u16,
 }
 
 impl PacketMetadata for Packet {
@@ -62,6 +81,23 @@ impl PacketMetadata for Packet {
     fn visited(&self) -> bool {
         self.visited.clone()
     }
+
+    fn seq(&self) -> Option<u32> {
+        self.seq.clone()
+    }
+
+    fn ack(&self) -> Option<u32> {
+        None
+    }
+
+    fn flags(&self) -> Option<u16> {
+        Some(self.flags)
+    }
+
+    // a millisecond between frames
+    fn timestamp(&self) -> Duration {
+        Duration::from_millis(self.number)
+    }
 }
 
 #[derive(Clone)]
@@ -95,6 +131,8 @@ fn packet_iter(descriptors: impl Iterator<Item = PacketDescriptor>) -> impl Iter
                     number: (number + 1) as _,
                     visited: false,
                     swapped: swap,
+                    seq: None,
+                    flags: TCP_FLAG_ACK | TCP_FLAG_PSH,
                 },
                 length,
             )
@@ -106,11 +144,13 @@ where
     T: TreePresenter,
 {
     let context = Context::new(0.0);
+    let mut cache = MessageCache::new();
     let _ = packet_iter(descriptors.iter().cloned())
         .fold((context, 0), |(mut context, pos), (metadata, length)| {
             let end = pos + length;
             if data.len() > end {
-                context.add(None, &data[pos..end], &metadata, output);
+                let slice = &data[pos..end];
+                context.add(&[], None, None, &mut cache, slice, &metadata, output);
             }
             (context, end)
         });
@@ -121,17 +161,62 @@ where
     T: TreePresenter,
 {
     let context = Context::new(0.0);
+    let mut cache = MessageCache::new();
     let _ = packet_iter(descriptors.iter().cloned())
         .fold((context, 0), |(mut context, pos), (metadata, length)| {
             let end = pos + length;
             if data.len() > end {
                 let chunk = BinaryChunk::from_content(&data[pos..end]).unwrap();
-                context.add(None, chunk.raw(), &metadata, output);
+                context.add(&[], None, None, &mut cache, chunk.raw(), &metadata, output);
             }
             (context, end)
         });
 }
 
+/// Decode the single plain chunk containing the `data` using the `encoding`,
+/// no conversation is needed, useful to measure the decoding alone.
+pub fn simulate_decoding<T>(encoding: &Encoding, data: &[u8], output: &mut T)
+where
+    T: TreePresenter,
+{
+    decode_chunk(encoding, data, output, |_| true)
+}
+
+/// The same, but estimate the size of the data before decoding it, like the decoding
+/// of the operations and the recursive encodings did, useful to compare with the single pass.
+#[cfg(feature = "bench")]
+pub fn simulate_estimated_decoding<T>(encoding: &Encoding, data: &[u8], output: &mut T)
+where
+    T: TreePresenter,
+{
+    decode_chunk(encoding, data, output, |chunked_data| {
+        estimate_size(chunked_data, encoding).is_ok()
+    })
+}
+
+// the `prepare` runs before the decoding, the decoding is skipped if it fails
+fn decode_chunk<T, F>(encoding: &Encoding, data: &[u8], output: &mut T, prepare: F)
+where
+    T: TreePresenter,
+    F: FnOnce(&mut ChunkedData<'_, ChunkInfo>) -> bool,
+{
+    let mut chunk = (data.len() as u16).to_be_bytes().to_vec();
+    chunk.extend_from_slice(data);
+    let chunks = [ChunkInfo::new(0, chunk.len())];
+    if let Some(mut chunked_data) = ChunkedData::new(&chunk, &chunks, 0) {
+        if !prepare(&mut chunked_data) {
+            return;
+        }
+        let space = 0..chunk.len();
+        let mut decoded = Decoded::root();
+        let result = show(&mut chunked_data, &space, encoding, "message", &mut decoded);
+        decoded.render(&space, output);
+        if let Err(e) = result {
+            output.add("decoding_error", 0..0, TreeLeaf::Display(e));
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct ChunkDescriptor {
     length: usize,
@@ -147,22 +232,51 @@ impl ChunkDescriptor {
     }
 }
 
+/// The identity from the `data` directory and the path to its file.
+pub(crate) fn test_identity() -> (Identity, String) {
+    let path = format!("{}/../data/identity.json", env!("CARGO_MANIFEST_DIR"));
+    let identity = Identity::from_path(&path).unwrap();
+    (identity, path)
+}
+
+/// The connection message of the node with the identity, the connection message
+/// of some remote node with a random key, and the secret key of the remote node.
+pub(crate) fn connection_chunks(
+    identity: &Identity,
+) -> (BinaryChunk, BinaryChunk, box_::SecretKey) {
+    let cm_local = identity.connection_message();
+    let (pk, sk) = box_::gen_keypair();
+    let cm_remote = ConnectionMessage::new(
+        4321,
+        &hex::encode(pk.as_ref()),
+        &hex::encode([0; 24]),
+        [0; 24].as_ref(),
+        cm_local.versions.clone(),
+    );
+    let chunk_local = BinaryChunk::from_content(&cm_local.as_bytes().unwrap()).unwrap();
+    let chunk_remote = BinaryChunk::from_content(&cm_remote.as_bytes().unwrap()).unwrap();
+    (chunk_local, chunk_remote, sk)
+}
+
+/// If `incoming` is true, the remote node connects to the node with the identity,
+/// so the identity is the responder, otherwise the identity is the initiator.
 pub fn simulate_encrypted<T>(
     descriptors: &[PacketDescriptor],
     initiator_chunk_descriptors: &[ChunkDescriptor],
     responder_chunk_descriptors: &[ChunkDescriptor],
     data: &[u8],
+    incoming: bool,
     output: &mut T,
 ) where
     T: TreePresenter,
 {
-    let path = format!("{}/../data/identity.json", env!("CARGO_MANIFEST_DIR"));
-    let identity = Identity::from_path(&path).unwrap();
-    let cm_a = identity.connection_message();
-    let (pk, _) = box_::gen_keypair();
-    let cm_b = ConnectionMessage::new(4321, &hex::encode(pk.as_ref()), &hex::encode([0; 24]), [0; 24].as_ref(), cm_a.versions.clone());
-    let chunk_a = BinaryChunk::from_content(&cm_a.as_bytes().unwrap()).unwrap();
-    let chunk_b = BinaryChunk::from_content(&cm_b.as_bytes().unwrap()).unwrap();
+    let (identity, path) = test_identity();
+    let (chunk_local, chunk_remote, _) = connection_chunks(&identity);
+    let (chunk_a, chunk_b) = if incoming {
+        (chunk_remote, chunk_local)
+    } else {
+        (chunk_local, chunk_remote)
+    };
     let (data_a, data_b) = encrypt_conversation(descriptors, data, initiator_chunk_descriptors, responder_chunk_descriptors, &chunk_a, &chunk_b, &identity);
 
     let handshake_descriptors = [
@@ -170,24 +284,27 @@ pub fn simulate_encrypted<T>(
         PacketDescriptor::new(chunk_b.content().len() + 2, true).unwrap(),
     ];
 
-    let id = (identity, path);
+    let id = [(identity, path)];
     let context = Context::new(0.0);
+    let mut cache = MessageCache::new();
     let _ = packet_iter(handshake_descriptors.iter().cloned().chain(descriptors.iter().cloned()))
-        .fold((context, 0, 0), |(mut context, pos_a, pos_b), (metadata, length)| {
-            let (end_a, end_b, slice) = if metadata.swapped {
+        .fold((context, 0, 0), |(mut context, pos_a, pos_b), (mut metadata, length)| {
+            let (end_a, end_b, slice, seq) = if metadata.swapped {
                 let end = pos_b + length;
                 if data_b.len() <= end {
                     return (context, pos_a, end)
                 }
-                (pos_a, end, &data_b[pos_b..end])
+                (pos_a, end, &data_b[pos_b..end], pos_b)
             } else {
                 let end = pos_a + length;
                 if data_a.len() <= end {
                     return (context, end, pos_b)
                 }
-                (end, pos_b, &data_a[pos_a..end])
+                (end, pos_b, &data_a[pos_a..end], pos_a)
             };
-            context.add(Some(&id), slice, &metadata, output);
+            // each direction has its own sequence numbers
+            metadata.seq = Some(seq as u32);
+            context.add(&id, None, None, &mut cache, slice, &metadata, output);
             (context, end_a, end_b)
         });
 }
@@ -251,10 +368,25 @@ fn encrypt_conversation(
 
 #[cfg(test)]
 mod tests {
-    use super::{simulate_encrypted, PacketDescriptor, ChunkDescriptor, Tree};
+    use wireshark_definitions::{SocketAddress, TCP_FLAG_ACK, TCP_FLAG_PSH, TCP_FLAG_FIN};
+    use std::time::Duration;
+    use super::{
+        simulate_encrypted, test_identity, connection_chunks, Packet, PacketDescriptor,
+        ChunkDescriptor, Tree,
+    };
+    use crate::{Context, MessageCache, NonceAddition::{Initiator, Responder}};
 
     #[test]
     fn basic() {
+        simulate(false)
+    }
+
+    #[test]
+    fn incoming() {
+        simulate(true)
+    }
+
+    fn simulate(incoming: bool) {
         let mut output = Tree::default().panic_on_decryption_error();
 
         let data = [93, 79, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 15, 64, 1, 95, 95, 95, 100, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 161, 160, 160, 160, 160, 160, 160, 153, 95, 95, 95, 95, 95, 95, 95, 95, 95, 93, 79, 0, 0, 0, 0, 0, 0, 0, 188, 188, 188, 188, 4, 0, 64, 1, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95, 95];
@@ -275,6 +407,81 @@ mod tests {
             ChunkDescriptor::new(12).unwrap(),
             ChunkDescriptor::new(10).unwrap(),
         ];
-        simulate_encrypted(descriptors.as_ref(), ic.as_ref(), rc.as_ref(), data.as_ref(), &mut output);
+        simulate_encrypted(descriptors.as_ref(), ic.as_ref(), rc.as_ref(), data.as_ref(), incoming, &mut output);
+    }
+
+    #[test]
+    fn message_split_across_frames() {
+        let (identity, path) = test_identity();
+        let (chunk_a, chunk_b, _) = connection_chunks(&identity);
+        let decipher = identity.decipher(chunk_a.raw(), chunk_b.raw()).ok().unwrap();
+        let encrypt = |plain: &[u8], nonce| {
+            let mut chunk = (plain.len() as u16 + 16).to_be_bytes().to_vec();
+            chunk.append(&mut decipher.encrypt(plain, nonce).unwrap());
+            chunk
+        };
+
+        // the metadata and the ack, then the length of the list of the messages,
+        // the tag of `GetCurrentBranch` and the chain id, split in two chunks
+        let message = [0, 0, 0, 6, 0, 0x10, 1, 2, 3, 4];
+        let frames = vec![
+            (false, chunk_a.raw().clone()),
+            (true, chunk_b.raw().clone()),
+            (false, [encrypt(&[0, 0], Initiator(0)), encrypt(&[0], Initiator(1))].concat()),
+            (true, [encrypt(&[0, 0], Responder(0)), encrypt(&[0], Responder(1))].concat()),
+            (false, encrypt(&message[..5], Initiator(2))),
+            (false, encrypt(&message[5..], Initiator(3))),
+        ];
+
+        let id = [(identity, path)];
+        // the connection might be closed by the frame with the first part of the message
+        for &closed in &[false, true] {
+            let mut context = Context::new(0.0);
+            let mut cache = MessageCache::new();
+            let mut output = Tree::default().panic_on_decryption_error();
+            // the first pass and the pass over the visited frames must count the same
+            for &visited in &[false, true] {
+                let (mut seq_a, mut seq_b) = (0, 0);
+                let summaries = frames
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &(swapped, ref data))| {
+                        let source = SocketAddress::Ip("132.132.132.132:1234".parse().unwrap());
+                        let destination =
+                            SocketAddress::Ip("123.123.123.123:1234".parse().unwrap());
+                        let seq = if swapped { &mut seq_b } else { &mut seq_a };
+                        let fin = if closed && i == 4 { TCP_FLAG_FIN } else { 0 };
+                        let metadata = Packet {
+                            source: if swapped { destination.clone() } else { source.clone() },
+                            destination: if swapped { source } else { destination },
+                            number: (i + 1) as _,
+                            visited,
+                            swapped,
+                            seq: Some(*seq),
+                            flags: TCP_FLAG_ACK | TCP_FLAG_PSH | fin,
+                        };
+                        *seq += data.len() as u32;
+                        context
+                            .add(&id, None, None, &mut cache, data, &metadata, &mut output)
+                            .unwrap()
+                    })
+                    .collect::<Vec<_>>();
+
+                // the first part of the message is not counted, and it is not an error,
+                // but the rest never arrives if the connection is closed
+                let first_part = summaries[4].to_string();
+                assert!(summaries[4].records().is_empty());
+                assert!(!first_part.contains("decoding error"));
+                assert_eq!(first_part.contains("buffering"), !visited && !closed);
+                assert_eq!(first_part.contains("unfinished"), !visited && closed);
+                let records = summaries[5].records();
+                assert_eq!(records.len(), 1);
+                assert_eq!(records[0].name, "GetCurrentBranch");
+                assert_eq!(records[0].direction, "local");
+                assert_eq!(records[0].size, message.len());
+                // the frames are a millisecond apart
+                assert_eq!(records[0].duration, Duration::from_millis(1));
+            }
+        }
     }
 }
@@ -1,6 +1,6 @@
 use failure::Fail;
 use std::ops::Range;
-use super::HasBodyRange;
+use super::{HasBodyRange, LAZY_DEPTH, protocol::Protocol};
 
 #[derive(Clone)]
 pub struct ChunkedData<'a, C>
@@ -26,6 +26,8 @@ where
                             chunks_offset: first_chunk_index,
                             limit: None,
                             limits: Vec::new(),
+                            lazy: Vec::new(),
+                            protocol: None,
                         },
                     });
                 }
@@ -51,6 +53,26 @@ where
         self.inner.limits.clear();
     }
 
+    /// The chunk and the offset in the data where the decoding continues.
+    pub fn position(&self) -> (usize, usize) {
+        (self.inner.chunks_offset, self.inner.data_offset)
+    }
+
+    /// Continue from the position, the data before it is already decoded.
+    pub fn set_position(&mut self, position: (usize, usize)) {
+        let (chunks_offset, data_offset) = position;
+        self.inner.chunks_offset = chunks_offset;
+        self.inner.data_offset = data_offset;
+        self.inner.limit = None;
+        self.inner.limits.clear();
+    }
+
+    /// Skip the data prefixed by its length, fails if there is not enough data.
+    pub fn skip_dynamic(&mut self) -> Result<(), DecodingError> {
+        let length = self.inner.get_u32()? as usize;
+        self.inner.advance(length).map(|_| ())
+    }
+
     pub fn on(&self, space: &Range<usize>) -> bool {
         self.inner
             .chunks
@@ -59,11 +81,21 @@ where
             .unwrap_or(false)
     }
 
+    /// The message is decoded, move to the chunk where the next message starts.
     pub fn complete_group<F>(&mut self, first_chunk: usize, warn: F)
     where
         F: Fn(),
     {
-        if self.inner.chunks_offset == first_chunk {
+        // the reading stays at the end of the last chunk, there is no next one yet
+        let consumed = self
+            .inner
+            .chunks
+            .get(self.inner.chunks_offset)
+            .map(|c| c.body().end == self.inner.data_offset)
+            .unwrap_or(false);
+        if consumed {
+            self.skip();
+        } else if self.inner.chunks_offset == first_chunk {
             self.skip();
             warn();
         }
@@ -72,6 +104,11 @@ where
             .for_each(C::set_continuation);
     }
 
+    /// The protocol the operations are decoded with, if none, they are shown as hex.
+    pub fn set_protocol(&mut self, protocol: Option<Protocol>) {
+        self.inner.protocol = protocol;
+    }
+
     pub fn inner_mut(&mut self) -> &mut ChunkedDataInner<'a, C> {
         &mut self.inner
     }
@@ -89,6 +126,80 @@ pub enum DecodingError {
     UnexpectedOptionDiscriminant,
     #[fail(display = "Path tag should be 0x00 or 0x0f or 0xf0")]
     BadPathTag,
+    #[fail(display = "Recursive encoding does not consume data")]
+    InfiniteRecursion,
+    #[fail(display = "Bad micheline expression: {}", _0)]
+    BadMicheline(&'static str),
+    #[fail(display = "Encoding not supported: {}", _0)]
+    NotSupported(&'static str),
+    // the offset is counted from the start of the message,
+    // the position is the offset in the data, so the failure can be highlighted
+    #[fail(
+        display = "{}, field: {}, encoding: {}, byte {} of the message",
+        cause, path, encoding, offset
+    )]
+    At {
+        cause: Box<DecodingError>,
+        path: String,
+        encoding: &'static str,
+        offset: usize,
+        position: usize,
+    },
+}
+
+impl DecodingError {
+    /// Attach the position and the encoding where the decoding failed,
+    /// only the innermost one is kept.
+    pub fn at(self, position: usize, encoding: &'static str) -> Self {
+        match self {
+            error @ DecodingError::At { .. } => error,
+            cause => DecodingError::At {
+                cause: Box::new(cause),
+                path: String::new(),
+                encoding,
+                offset: position,
+                position,
+            },
+        }
+    }
+
+    /// Prepend the name of the field containing the failure to the path,
+    /// the field that just passes the data to the nested encoding has the same name.
+    pub fn within(self, name: &str) -> Self {
+        match self {
+            DecodingError::At {
+                cause,
+                path,
+                encoding,
+                offset,
+                position,
+            } => {
+                let path = if path.is_empty() {
+                    name.to_owned()
+                } else if path.split('.').next() == Some(name) {
+                    path
+                } else {
+                    format!("{}.{}", name, path)
+                };
+                DecodingError::At {
+                    cause,
+                    path,
+                    encoding,
+                    offset,
+                    position,
+                }
+            },
+            error => error,
+        }
+    }
+
+    /// The offset in the data where the decoding failed.
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            &DecodingError::At { position, .. } => Some(position),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -102,6 +213,9 @@ where
     chunks_offset: usize,
     limit: Option<usize>,
     limits: Vec<Option<usize>>,
+    // lazy encodings being decoded, the offset where each started
+    lazy: Vec<usize>,
+    protocol: Option<Protocol>,
 }
 
 macro_rules! primitive {
@@ -238,6 +352,42 @@ where
         self.data_offset
     }
 
+    /// The number of bytes of the message between the offsets,
+    /// the chunk headers and MACs are not counted.
+    pub fn distance(&self, start: usize, end: usize) -> usize {
+        self.chunks
+            .iter()
+            .map(|c| c.body())
+            .map(|body| usize::min(body.end, end).saturating_sub(usize::max(body.start, start)))
+            .sum()
+    }
+
+    /// Start decoding the lazy encoding, fails if too many lazy encodings are started
+    /// at the same offset, such recursion would never end.
+    pub fn push_lazy(&mut self) -> Result<(), DecodingError> {
+        let offset = self.offset();
+        let same_offset = self.lazy.iter().filter(|&&o| o == offset).count();
+        if same_offset >= LAZY_DEPTH {
+            Err(DecodingError::InfiniteRecursion)
+        } else {
+            self.lazy.push(offset);
+            Ok(())
+        }
+    }
+
+    pub fn pop_lazy(&mut self) {
+        self.lazy.pop();
+    }
+
+    /// If the lazy encodings are nested deeper than the fields are registered.
+    pub fn is_lazy_nested(&self) -> bool {
+        self.lazy.len() >= LAZY_DEPTH
+    }
+
+    pub fn protocol(&self) -> Option<Protocol> {
+        self.protocol
+    }
+
     pub fn following(&self, length: usize) -> Range<usize> {
         self.offset()..(self.offset() + length)
     }
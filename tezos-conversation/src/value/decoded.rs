@@ -0,0 +1,141 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use wireshark_definitions::{TreePresenter, TreeLeaf};
+use std::{fmt, mem, ops::Range};
+use crate::range_tool::intersect;
+
+/// The node of the decoded message. The message is decoded in a single pass,
+/// the range of the node is known only when all its children are decoded,
+/// so the tree is collected first, and rendered after.
+pub struct Decoded {
+    name: String,
+    range: Range<usize>,
+    leaf: TreeLeaf<String>,
+    children: Vec<Decoded>,
+}
+
+impl Decoded {
+    /// The container of the top level nodes, it is not rendered itself.
+    pub fn root() -> Self {
+        Decoded {
+            name: String::new(),
+            range: 0..0,
+            leaf: TreeLeaf::Nothing,
+            children: Vec::new(),
+        }
+    }
+
+    /// Add the child node and return it, so more nodes can be added in it.
+    pub fn add<D, N>(&mut self, name: N, range: Range<usize>, v: TreeLeaf<D>) -> &mut Self
+    where
+        D: fmt::Display,
+        N: AsRef<str>,
+    {
+        let leaf = match v {
+            TreeLeaf::Nothing => TreeLeaf::Nothing,
+            TreeLeaf::Display(value) => TreeLeaf::Display(value.to_string()),
+            TreeLeaf::Int64Dec(value) => TreeLeaf::Int64Dec(value),
+            TreeLeaf::Uint64Dec(value) => TreeLeaf::Uint64Dec(value),
+            TreeLeaf::Boolean(value) => TreeLeaf::Boolean(value),
+            TreeLeaf::Bytes(value) => TreeLeaf::Bytes(value),
+            TreeLeaf::Float64(value) => TreeLeaf::Float64(value),
+            TreeLeaf::AbsoluteTime(value) => TreeLeaf::AbsoluteTime(value),
+            TreeLeaf::FrameNum(value) => TreeLeaf::FrameNum(value),
+            TreeLeaf::Ipv4(value) => TreeLeaf::Ipv4(value),
+            TreeLeaf::Ipv6(value) => TreeLeaf::Ipv6(value),
+        };
+        self.children.push(Decoded {
+            name: name.as_ref().to_owned(),
+            range,
+            leaf,
+            children: Vec::new(),
+        });
+        self.children.last_mut().unwrap()
+    }
+
+    /// Move the nodes decoded aside in this node.
+    pub fn append(&mut self, other: Decoded) {
+        let mut other = other;
+        self.children.append(&mut other.children);
+    }
+
+    /// The range of the composite node, it is set when the children are decoded.
+    pub fn set_range(&mut self, range: Range<usize>) {
+        self.range = range;
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn children(&self) -> &[Decoded] {
+        &self.children
+    }
+
+    /// The approximate amount of memory the node occupies.
+    pub fn size(&self) -> usize {
+        let leaf = match &self.leaf {
+            &TreeLeaf::Display(ref value) => value.capacity(),
+            &TreeLeaf::Bytes(ref value) => value.capacity(),
+            _ => 0,
+        };
+        let children = self.children.iter().map(Decoded::size).sum::<usize>();
+        mem::size_of::<Self>() + self.name.capacity() + leaf + children
+    }
+
+    /// Render the children in the node, the ranges are expressed using space as the origin.
+    pub fn render<P>(&self, space: &Range<usize>, node: &mut P)
+    where
+        P: TreePresenter,
+    {
+        for child in &self.children {
+            let leaf = match &child.leaf {
+                &TreeLeaf::Nothing => TreeLeaf::Nothing,
+                &TreeLeaf::Display(ref value) => TreeLeaf::Display(value.as_str()),
+                &TreeLeaf::Int64Dec(value) => TreeLeaf::Int64Dec(value),
+                &TreeLeaf::Uint64Dec(value) => TreeLeaf::Uint64Dec(value),
+                &TreeLeaf::Boolean(value) => TreeLeaf::Boolean(value),
+                &TreeLeaf::Bytes(ref value) => TreeLeaf::Bytes(value.clone()),
+                &TreeLeaf::Float64(value) => TreeLeaf::Float64(value),
+                &TreeLeaf::AbsoluteTime(value) => TreeLeaf::AbsoluteTime(value),
+                &TreeLeaf::FrameNum(value) => TreeLeaf::FrameNum(value),
+                &TreeLeaf::Ipv4(value) => TreeLeaf::Ipv4(value),
+                &TreeLeaf::Ipv6(value) => TreeLeaf::Ipv6(value),
+            };
+            let range = intersect(space, child.range.clone());
+            let mut child_node = node.add(&child.name, range, leaf);
+            if !child.children.is_empty() {
+                child.render(space, &mut child_node.subtree());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tezos_encoding::encoding::{Encoding, Field};
+    use super::{
+        super::{ChunkedData, Plain, show},
+        Decoded,
+    };
+
+    #[test]
+    fn ranges_known_after_decoding() {
+        let encoding = Encoding::Obj(vec![
+            Field::new("a", Encoding::Int32),
+            Field::new("b", Encoding::Tup(vec![Encoding::Uint8, Encoding::Uint16])),
+        ]);
+        let data = [0; 7];
+        let chunks = [Plain(0..data.len())];
+        let mut data = ChunkedData::new(data.as_ref(), chunks.as_ref(), 0).unwrap();
+        let mut decoded = Decoded::root();
+        show(&mut data, &(0..7), &encoding, "message", &mut decoded).unwrap();
+
+        let message = &decoded.children[0];
+        assert_eq!(message.range, 0..7);
+        assert_eq!(message.children[0].range, 0..4);
+        assert_eq!(message.children[1].range, 4..7);
+        assert_eq!(message.children[1].children[1].range, 5..7);
+    }
+}
@@ -3,6 +3,11 @@
 
 use tezos_encoding::encoding::{HasEncoding, Encoding, SchemaType, Field};
 use wireshark_definitions::{FieldDescriptorOwned, FieldDescriptor, HasFields};
+use super::{
+    LAZY_DEPTH,
+    micheline::MICHELINE_DEPTH,
+    protocol::{Protocol, SIGNATURE_SIZE, NATURAL_FIELDS, is_operation, is_micheline},
+};
 
 /// The wrapper around the type which has an encoding and a name as a static string.
 /// The wrapper needed because it is impossible to implement foreign trait for foreign type.
@@ -18,6 +23,11 @@ enum FieldKind {
     Nothing,
     String,
     IntDec,
+    UintDec,
+    Boolean,
+    Bytes,
+    Double,
+    Time,
 }
 
 /// Create `FieldDescriptorOwned` the structure of wireshark-epan-adapter
@@ -40,9 +50,28 @@ fn to_descriptor(base: &str, this: &str, kind: FieldKind) -> FieldDescriptorOwne
         FieldKind::Nothing => FieldDescriptorOwned::Nothing { name, abbrev },
         FieldKind::String => FieldDescriptorOwned::String { name, abbrev },
         FieldKind::IntDec => FieldDescriptorOwned::Int64Dec { name, abbrev },
+        FieldKind::UintDec => FieldDescriptorOwned::Uint64Dec { name, abbrev },
+        FieldKind::Boolean => FieldDescriptorOwned::Boolean { name, abbrev },
+        FieldKind::Bytes => FieldDescriptorOwned::Bytes { name, abbrev },
+        FieldKind::Double => FieldDescriptorOwned::Double { name, abbrev },
+        FieldKind::Time => FieldDescriptorOwned::AbsoluteTime { name, abbrev },
     }
 }
 
+/// The parts of the Micheline expression, and the nested expressions until the depth is zero.
+fn micheline(base: &str, depth: usize) -> Vec<FieldDescriptorOwned> {
+    let mut fields = ["int", "string", "prim", "annots"]
+        .iter()
+        .map(|name| to_descriptor(base, name, FieldKind::String))
+        .chain(std::iter::once(to_descriptor(base, "bytes", FieldKind::Bytes)))
+        .collect::<Vec<_>>();
+    if depth > 0 {
+        fields.push(to_descriptor(base, "expr", FieldKind::String));
+        fields.extend(micheline(&format!("{}.expr", base), depth - 1));
+    }
+    fields
+}
+
 // Wireshark requires all tree branches are registered before dissector run.
 // We need to resister all types that we expect to decode before start.
 impl<T> HasFields for TezosEncoded<T>
@@ -68,30 +97,60 @@ where
         //          abbrev: "tezos.metadata_message.private_node\u{0}",
         //      },
         //  ]
-        fn recursive(base: &str, name: &str, encoding: &Encoding) -> Vec<FieldDescriptorOwned> {
+        fn recursive(
+            base: &str,
+            name: &str,
+            encoding: &Encoding,
+            // how many lazy encodings contain this one
+            lazy: usize,
+        ) -> Vec<FieldDescriptorOwned> {
             let new_base = format!("{}.{}", base, name);
             let (kind, more) = match encoding {
-                &Encoding::Unit => (None, Vec::new()),
+                // the tezedge encoding does not carry the bounds, the ranged integer is not decoded
+                &Encoding::Unit | &Encoding::RangedInt => (None, Vec::new()),
                 &Encoding::Int8
-                | &Encoding::Uint8
                 | &Encoding::Int16
-                | &Encoding::Uint16
                 | &Encoding::Int31
                 | &Encoding::Int32
-                | &Encoding::Uint32
-                | &Encoding::Int64
-                | &Encoding::RangedInt => (Some(FieldKind::IntDec), Vec::new()),
-                &Encoding::Z | &Encoding::Mutez => (Some(FieldKind::String), Vec::new()),
-                &Encoding::Float | &Encoding::RangedFloat => unimplemented!(),
-                &Encoding::Bool => (Some(FieldKind::String), Vec::new()),
-                &Encoding::String | &Encoding::Bytes => (Some(FieldKind::String), Vec::new()),
+                | &Encoding::Int64 => (Some(FieldKind::IntDec), Vec::new()),
+                // the enum is shown as its number
+                &Encoding::Uint8 | &Encoding::Uint16 | &Encoding::Uint32 | &Encoding::Enum => {
+                    (Some(FieldKind::UintDec), Vec::new())
+                },
+                // the value is a number if it fits in 64 bits, otherwise it is `exact` string
+                &Encoding::Z => (
+                    Some(FieldKind::IntDec),
+                    vec![to_descriptor(&new_base, "exact", FieldKind::String)],
+                ),
+                &Encoding::Mutez => (
+                    Some(FieldKind::IntDec),
+                    if NATURAL_FIELDS.contains(&name) {
+                        vec![to_descriptor(&new_base, "exact", FieldKind::String)]
+                    } else {
+                        vec![
+                            to_descriptor(&new_base, "exact", FieldKind::String),
+                            to_descriptor(&new_base, "tez", FieldKind::String),
+                        ]
+                    },
+                ),
+                &Encoding::Float | &Encoding::RangedFloat => {
+                    (Some(FieldKind::Double), Vec::new())
+                },
+                &Encoding::Bool => (Some(FieldKind::Boolean), Vec::new()),
+                &Encoding::String => (Some(FieldKind::String), Vec::new()),
+                &Encoding::Bytes => (Some(FieldKind::Bytes), Vec::new()),
                 &Encoding::Tags(ref size, ref map) => (
                     Some(FieldKind::Nothing),
                     // have to probe all ids...
                     (0..=(((1usize << (size.clone() * 8)) - 1) as u16))
                         .filter_map(|id| map.find_by_id(id))
                         .map(|tag| {
-                            recursive(new_base.as_str(), tag.get_variant(), tag.get_encoding())
+                            let (variant, encoding) = (tag.get_variant(), tag.get_encoding());
+                            if let &Encoding::Unit = encoding {
+                                vec![to_descriptor(&new_base, variant, FieldKind::Nothing)]
+                            } else {
+                                recursive(new_base.as_str(), variant, encoding, lazy)
+                            }
                         })
                         .flatten()
                         .collect(),
@@ -100,19 +159,32 @@ where
                     // list of uint8 can be presented as hex, just like `Encoding::Bytes`
                     // `Encoding::List(Encoding::Uint8)` is the same as `Encoding::Bytes`
                     if let &Encoding::Uint8 = encoding.as_ref() {
-                        (Some(FieldKind::String), Vec::new())
+                        (Some(FieldKind::Bytes), Vec::new())
                     } else {
-                        (None, recursive(base, name, encoding))
+                        (None, recursive(base, name, encoding, lazy))
                     }
                 },
-                &Encoding::Enum => (Some(FieldKind::String), Vec::new()),
                 &Encoding::Option(ref encoding) | &Encoding::OptionalField(ref encoding) => {
-                    (None, recursive(base, name, encoding))
+                    (None, recursive(base, name, encoding, lazy))
                 },
                 &Encoding::Obj(ref fields) => (
                     Some(FieldKind::Nothing),
                     if fields.len() == 1 && fields[0].get_name() == "messages" {
-                        recursive(base, name, &fields[0].get_encoding())
+                        recursive(base, name, &fields[0].get_encoding(), lazy)
+                    } else if is_operation(fields) {
+                        // the data is either shown as is, or decoded as the contents,
+                        // all supported protocols have the same encoding of the contents
+                        let contents = Encoding::list(Protocol::Proto007.contents_encoding());
+                        let signature = Encoding::sized(SIGNATURE_SIZE, Encoding::Bytes);
+                        fields
+                            .iter()
+                            .map(|f| (f.get_name().as_str(), f.get_encoding().clone()))
+                            .chain(vec![("contents", contents), ("signature", signature)])
+                            .map(|(name, encoding)| {
+                                recursive(new_base.as_str(), name, &encoding, lazy)
+                            })
+                            .flatten()
+                            .collect()
                     } else {
                         fields
                             .iter()
@@ -127,7 +199,25 @@ where
                                 } else {
                                     field.get_encoding().clone()
                                 };
-                                recursive(new_base.as_str(), field.get_name(), &encoding)
+                                let name = field.get_name();
+                                let mut descriptors = recursive(&new_base, name, &encoding, lazy);
+                                // the decoded protocol data goes in the subtree of the hex
+                                if name == "protocol_data" {
+                                    let base = format!("{}.{}", new_base, name);
+                                    let protocol = Protocol::Proto007;
+                                    for f in protocol.protocol_data_fields() {
+                                        let (n, e) = (f.get_name(), f.get_encoding());
+                                        descriptors.extend(recursive(&base, n, e, lazy));
+                                    }
+                                }
+                                // the expression is shown as the string, or hex if malformed
+                                if is_micheline(fields, name) {
+                                    let base = format!("{}.{}", new_base, name);
+                                    let kind = FieldKind::String;
+                                    descriptors = vec![to_descriptor(&new_base, name, kind)];
+                                    descriptors.extend(micheline(&base, MICHELINE_DEPTH));
+                                }
+                                descriptors
                             })
                             .flatten()
                             .collect()
@@ -139,28 +229,88 @@ where
                         .enumerate()
                         .map(|(i, encoding)| {
                             let n = format!("{}", i);
-                            recursive(new_base.as_str(), &n, encoding)
+                            recursive(new_base.as_str(), &n, encoding, lazy)
                         })
                         .flatten()
                         .collect(),
                 ),
-                &Encoding::Dynamic(ref encoding) => (None, recursive(base, name, encoding)),
-                &Encoding::Sized(_, ref encoding) => (None, recursive(base, name, encoding)),
-                &Encoding::Greedy(ref encoding) => (None, recursive(base, name, encoding)),
-                &Encoding::Hash(_) => (Some(FieldKind::String), Vec::new()),
-                &Encoding::Split(ref f) => (None, recursive(base, name, &f(SchemaType::Binary))),
-                &Encoding::Timestamp => (Some(FieldKind::String), Vec::new()),
+                &Encoding::Dynamic(ref encoding) => {
+                    (None, recursive(base, name, encoding, lazy))
+                },
+                &Encoding::Sized(_, ref encoding) => {
+                    (None, recursive(base, name, encoding, lazy))
+                },
+                &Encoding::Greedy(ref encoding) => {
+                    (None, recursive(base, name, encoding, lazy))
+                },
+                &Encoding::Hash(_) => (
+                    Some(FieldKind::String),
+                    vec![to_descriptor(&new_base, "hex", FieldKind::Bytes)],
+                ),
+                &Encoding::Split(ref f) => {
+                    (None, recursive(base, name, &f(SchemaType::Binary), lazy))
+                },
+                &Encoding::Timestamp => (Some(FieldKind::Time), Vec::new()),
                 // it is impossible to traversal infinite tree,
-                // so it should be replaced by something else
-                // the only such situation is `operation_hashes_path`,
-                // it treated as special case, see above
-                &Encoding::Lazy(ref _f) => panic!("should workaround somehow infinite tree"),
+                // so the lazy encoding nested too deep is shown as hex,
+                // `operation_hashes_path` is treated as special case, see above
+                &Encoding::Lazy(ref f) => {
+                    if lazy >= LAZY_DEPTH {
+                        (Some(FieldKind::Bytes), Vec::new())
+                    } else {
+                        (None, recursive(base, name, &f(), lazy + 1))
+                    }
+                },
             };
             kind.map(|kind| to_descriptor(base, name, kind))
                 .into_iter()
                 .chain(more)
                 .collect()
         }
-        recursive("tezos.messages", T::NAME, &T::encoding())
+        recursive("tezos.messages", T::NAME, &T::encoding(), 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use tezos_encoding::encoding::{HasEncoding, Encoding, Field};
+    use wireshark_definitions::{HasFields, FieldDescriptorOwned};
+    use super::{super::LAZY_DEPTH, TezosEncoded, Named};
+
+    fn recursive() -> Encoding {
+        Encoding::Obj(vec![Field::new("next", Encoding::Lazy(Arc::new(recursive)))])
+    }
+
+    struct Recursive;
+
+    impl HasEncoding for Recursive {
+        fn encoding() -> Encoding {
+            recursive()
+        }
+    }
+
+    impl Named for Recursive {
+        const NAME: &'static str = "recursive";
+    }
+
+    #[test]
+    fn recursive_lazy_fields() {
+        let fields = TezosEncoded::<Recursive>::fields();
+        // the message itself, the objects nested by the lazy encoding until the depth limit,
+        // and the innermost one is hex
+        assert_eq!(fields.len(), LAZY_DEPTH + 2);
+        for (depth, field) in fields.iter().enumerate() {
+            let expected = format!("tezos.messages.recursive{}\0", ".next".repeat(depth));
+            match field {
+                &FieldDescriptorOwned::Nothing { ref abbrev, .. } if depth <= LAZY_DEPTH => {
+                    assert_eq!(abbrev, &expected)
+                },
+                &FieldDescriptorOwned::Bytes { ref abbrev, .. } if depth == LAZY_DEPTH + 1 => {
+                    assert_eq!(abbrev, &expected)
+                },
+                _ => panic!("unexpected field at depth {}", depth),
+            }
+        }
     }
 }
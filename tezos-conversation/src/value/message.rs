@@ -1,21 +1,24 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use tezos_encoding::encoding::{Encoding, SchemaType};
-use wireshark_definitions::{TreePresenter, TreeLeaf};
-use chrono::NaiveDateTime;
+use tezos_encoding::encoding::{Encoding, SchemaType, Field};
+use wireshark_definitions::TreeLeaf;
 use std::ops::Range;
-use bit_vec::BitVec;
+use num_bigint::{BigInt, BigUint, Sign};
+use std::convert::TryFrom;
 use crypto::hash::HashType;
 use crate::range_tool::intersect;
 use super::{
     chunked_data::{ChunkedData, ChunkedDataInner, DecodingError},
+    decoded::Decoded,
+    micheline::{Expression, MICHELINE_DEPTH},
+    protocol::{SIGNATURE_SIZE, NATURAL_FIELDS, ENTRYPOINT_NAME, is_operation, is_micheline},
     HasBodyRange,
 };
 
 pub trait TezosReader {
-    fn read_z(&mut self) -> Result<String, DecodingError>;
-    fn read_mutez(&mut self) -> Result<String, DecodingError>;
+    fn read_z(&mut self) -> Result<BigInt, DecodingError>;
+    fn read_mutez(&mut self) -> Result<BigUint, DecodingError>;
     fn read_path(&mut self, v: &mut Vec<String>) -> Result<(), DecodingError>;
 }
 
@@ -24,81 +27,36 @@ where
     ChunkedData<'a, C>: Clone,
     C: HasBodyRange,
 {
-    fn read_z(&mut self) -> Result<String, DecodingError> {
-        // read first byte
+    fn read_z(&mut self) -> Result<BigInt, DecodingError> {
+        // the first byte holds the continuation bit, the sign bit and 6 bits of the value,
+        // each next byte holds the continuation bit and 7 bits of the value, little endian
         let byte = self.get_u8()?;
-        let negative = byte & (1 << 6) != 0;
-        if byte <= 0x3F {
-            let mut num = i32::from(byte);
-            if negative {
-                num *= -1;
-            }
-            Ok(format!("{:x}", num))
-        } else {
-            let mut bits = BitVec::new();
-            for bit_idx in 0..6 {
-                bits.push(byte & (1 << bit_idx) != 0);
-            }
-
-            let mut has_next_byte = true;
-            while has_next_byte {
-                let byte = self.get_u8()?;
-                for bit_idx in 0..7 {
-                    bits.push(byte & (1 << bit_idx) != 0)
-                }
-
-                has_next_byte = byte & (1 << 7) != 0;
-            }
-
-            let bytes = to_byte_vec(&trim_left(&reverse(&bits)));
-
-            let mut str_num = bytes
-                .iter()
-                .enumerate()
-                .map(|(idx, b)| match idx {
-                    0 => format!("{:x}", *b),
-                    _ => format!("{:02x}", *b),
-                })
-                .fold(String::new(), |mut str_num, val| {
-                    str_num.push_str(&val);
-                    str_num
-                });
-            if negative {
-                str_num = String::from("-") + str_num.as_str();
-            }
-
-            Ok(str_num)
+        let negative = byte & 0x40 != 0;
+        let mut value = BigUint::from(byte & 0x3f);
+        let mut shift = 6;
+        let mut has_next_byte = byte & 0x80 != 0;
+        while has_next_byte {
+            let byte = self.get_u8()?;
+            value |= BigUint::from(byte & 0x7f) << shift;
+            shift += 7;
+            has_next_byte = byte & 0x80 != 0;
         }
+        let sign = if negative { Sign::Minus } else { Sign::Plus };
+        Ok(BigInt::from_biguint(sign, value))
     }
 
-    fn read_mutez(&mut self) -> Result<String, DecodingError> {
-        let mut bits = BitVec::new();
-
+    fn read_mutez(&mut self) -> Result<BigUint, DecodingError> {
+        // same as `Z`, but without the sign bit
+        let mut value = BigUint::from(0u8);
+        let mut shift = 0;
         let mut has_next_byte = true;
         while has_next_byte {
             let byte = self.get_u8()?;
-            for bit_idx in 0..7 {
-                bits.push(byte & (1 << bit_idx) != 0)
-            }
-
-            has_next_byte = byte & (1 << 7) != 0;
+            value |= BigUint::from(byte & 0x7f) << shift;
+            shift += 7;
+            has_next_byte = byte & 0x80 != 0;
         }
-
-        let bytes = to_byte_vec(&trim_left(&reverse(&bits)));
-
-        let str_num = bytes
-            .iter()
-            .enumerate()
-            .map(|(idx, b)| match idx {
-                0 => format!("{:x}", *b),
-                _ => format!("{:02x}", *b),
-            })
-            .fold(String::new(), |mut str_num, val| {
-                str_num.push_str(&val);
-                str_num
-            });
-
-        Ok(str_num)
+        Ok(value)
     }
 
     fn read_path(&mut self, v: &mut Vec<String>) -> Result<(), DecodingError> {
@@ -106,14 +64,14 @@ where
             0x00 => Ok(()),
             0xf0 => {
                 self.read_path(v)?;
-                let l = HashType::OperationListListHash.size();
-                let hash = hex::encode(self.copy_to_vec(l)?);
+                let h = HashType::OperationListListHash;
+                let hash = h.bytes_to_string(&self.copy_to_vec(h.size())?);
                 v.push(format!("left: {}", hash));
                 Ok(())
             },
             0x0f => {
-                let l = HashType::OperationListListHash.size();
-                let hash = hex::encode(self.copy_to_vec(l)?);
+                let h = HashType::OperationListListHash;
+                let hash = h.bytes_to_string(&self.copy_to_vec(h.size())?);
                 self.read_path(v)?;
                 v.push(format!("right: {}", hash));
                 Ok(())
@@ -123,30 +81,137 @@ where
     }
 }
 
-pub fn show<'a, C, P>(
+/// Show the arbitrary precision integer as a number if it fits in 64 bits,
+/// otherwise show its exact decimal representation in the `exact` field.
+/// The mutez amount also has the `tez` field.
+fn show_big_int(
+    node: &mut Decoded,
+    base: &str,
+    range: Range<usize>,
+    value: &BigInt,
+    tez: Option<String>,
+) {
+    match i64::try_from(value) {
+        Ok(value) => {
+            let sub_node = node.add(base, range.clone(), TreeLeaf::dec(value));
+            if let Some(tez) = tez {
+                sub_node.add("tez", range, TreeLeaf::Display(tez));
+            }
+        },
+        Err(_) => {
+            let exact = format!("{}.exact", base);
+            node.add(exact, range.clone(), TreeLeaf::Display(value));
+            if let Some(tez) = tez {
+                node.add(format!("{}.tez", base), range, TreeLeaf::Display(tez));
+            }
+        },
+    }
+}
+
+pub fn show<'a, C>(
     data: &mut ChunkedData<'a, C>,
     space: &Range<usize>,
     encoding: &Encoding,
     base: &str,
-    node: &mut P,
+    node: &mut Decoded,
 ) -> Result<(), DecodingError>
 where
     C: HasBodyRange + Clone,
-    P: TreePresenter,
 {
-    show_inner(data.inner_mut(), space, encoding, base, node)
+    let data = data.inner_mut();
+    let start = data.offset();
+    show_inner(data, space, encoding, base, node).map_err(|e| locate(data, start, e))
 }
 
-pub fn show_inner<'a, C, P>(
+/// Count the offset of the failure from the start of the message.
+fn locate<'a, C>(
+    data: &ChunkedDataInner<'a, C>,
+    start: usize,
+    error: DecodingError,
+) -> DecodingError
+where
+    C: HasBodyRange,
+{
+    match error {
+        DecodingError::At {
+            cause,
+            path,
+            encoding,
+            position,
+            ..
+        } => DecodingError::At {
+            cause,
+            path,
+            encoding,
+            offset: data.distance(start, position),
+            position,
+        },
+        error => error,
+    }
+}
+
+/// The name of the encoding shown in the decoding error.
+fn encoding_name(encoding: &Encoding) -> &'static str {
+    match encoding {
+        &Encoding::Unit => "unit",
+        &Encoding::Int8 => "int8",
+        &Encoding::Uint8 => "uint8",
+        &Encoding::Int16 => "int16",
+        &Encoding::Uint16 => "uint16",
+        &Encoding::Int31 => "int31",
+        &Encoding::Int32 => "int32",
+        &Encoding::Uint32 => "uint32",
+        &Encoding::Int64 => "int64",
+        &Encoding::RangedInt => "ranged int",
+        &Encoding::Z => "z",
+        &Encoding::Mutez => "mutez",
+        &Encoding::Float => "float",
+        &Encoding::RangedFloat => "ranged float",
+        &Encoding::Bool => "bool",
+        &Encoding::String => "string",
+        &Encoding::Bytes => "bytes",
+        &Encoding::Tags(..) => "tags",
+        &Encoding::List(_) => "list",
+        &Encoding::Enum => "enum",
+        &Encoding::Option(_) => "option",
+        &Encoding::OptionalField(_) => "optional field",
+        &Encoding::Obj(_) => "object",
+        &Encoding::Tup(_) => "tuple",
+        &Encoding::Dynamic(_) => "dynamic",
+        &Encoding::Sized(..) => "sized",
+        &Encoding::Greedy(_) => "greedy",
+        &Encoding::Hash(_) => "hash",
+        &Encoding::Split(_) => "split",
+        &Encoding::Timestamp => "timestamp",
+        &Encoding::Lazy(_) => "lazy",
+    }
+}
+
+/// Show the value, if it fails, the error knows the field and the encoding that failed.
+pub fn show_inner<'a, C>(
     data: &mut ChunkedDataInner<'a, C>,
     space: &Range<usize>,
     encoding: &Encoding,
     base: &str,
-    node: &mut P,
+    node: &mut Decoded,
+) -> Result<(), DecodingError>
+where
+    C: HasBodyRange + Clone,
+{
+    let position = data.offset();
+    show_encoding(data, space, encoding, base, node)
+        .map_err(|e| e.at(position, encoding_name(encoding)).within(base))
+}
+
+fn show_encoding<'a, C>(
+    data: &mut ChunkedDataInner<'a, C>,
+    space: &Range<usize>,
+    encoding: &Encoding,
+    base: &str,
+    node: &mut Decoded,
 ) -> Result<(), DecodingError>
 where
     C: HasBodyRange + Clone,
-    P: TreePresenter,
 {
     match encoding {
         &Encoding::Unit => (),
@@ -158,7 +223,7 @@ where
         &Encoding::Uint8 => {
             let item = data.following(1);
             let value = data.get_u8()?;
-            node.add(base, intersect(space, item), TreeLeaf::dec(value as _));
+            node.add(base, intersect(space, item), TreeLeaf::uint(value.into()));
         },
         &Encoding::Int16 => {
             let item = data.following(2);
@@ -168,7 +233,7 @@ where
         &Encoding::Uint16 => {
             let item = data.following(2);
             let value = data.get_u16()?;
-            node.add(base, intersect(space, item), TreeLeaf::dec(value as _));
+            node.add(base, intersect(space, item), TreeLeaf::uint(value.into()));
         },
         &Encoding::Int31 | &Encoding::Int32 => {
             let item = data.following(4);
@@ -178,36 +243,45 @@ where
         &Encoding::Uint32 => {
             let item = data.following(4);
             let value = data.get_u32()?;
-            node.add(base, intersect(space, item), TreeLeaf::dec(value.into()));
+            node.add(base, intersect(space, item), TreeLeaf::uint(value.into()));
         },
         &Encoding::Int64 => {
             let item = data.following(8);
             let value = data.get_i64()?;
             node.add(base, intersect(space, item), TreeLeaf::dec(value as _));
         },
-        &Encoding::RangedInt => unimplemented!(),
+        // the tezedge encoding does not carry the bounds, so the size is unknown
+        &Encoding::RangedInt => return Err(DecodingError::NotSupported("ranged int")),
         &Encoding::Z => {
             let mut item = data.following(0);
             let value = data.read_z()?;
             item.end = data.offset();
-            node.add(base, intersect(space, item), TreeLeaf::Display(value));
+            show_big_int(node, base, intersect(space, item), &value, None);
         },
         &Encoding::Mutez => {
             let mut item = data.following(0);
             let value = data.read_mutez()?;
             item.end = data.offset();
-            node.add(base, intersect(space, item), TreeLeaf::Display(value));
+            // the natural numbers of the operation are decoded as mutez, but they are not amounts
+            let tez = if NATURAL_FIELDS.contains(&base) {
+                None
+            } else {
+                let tez = format!("{}.{:06} tez", &value / 1_000_000u32, &value % 1_000_000u32);
+                Some(tez)
+            };
+            let value = BigInt::from(value);
+            show_big_int(node, base, intersect(space, item), &value, tez);
         },
-        &Encoding::Float => {
+        // the ranged float is encoded just as a float, the range is only checked
+        &Encoding::Float | &Encoding::RangedFloat => {
             let item = data.following(8);
             let value = data.get_f64()?;
             node.add(base, intersect(space, item), TreeLeaf::float(value as _));
         },
-        &Encoding::RangedFloat => unimplemented!(),
         &Encoding::Bool => {
             let item = data.following(1);
             let value = data.get_u8()? == 0xff;
-            node.add(base, intersect(space, item), TreeLeaf::Display(value));
+            node.add(base, intersect(space, item), TreeLeaf::boolean(value));
         },
         &Encoding::String => {
             let mut item = data.following(4);
@@ -220,8 +294,8 @@ where
         },
         &Encoding::Bytes => {
             let item = data.following(data.remaining());
-            let string = hex::encode(data.copy_to_vec(item.len())?);
-            node.add(base, intersect(space, item), TreeLeaf::Display(string));
+            let bytes = data.copy_to_vec(item.len())?;
+            node.add(base, intersect(space, item), TreeLeaf::bytes(bytes));
         },
         &Encoding::Tags(ref tag_size, ref tag_map) => {
             let id = match tag_size {
@@ -231,12 +305,17 @@ where
             };
             if let Some(tag) = tag_map.find_by_id(id) {
                 let encoding = tag.get_encoding();
-                let size = estimate_size(data, encoding)?;
-                let item = data.following(size);
-                let range = intersect(space, item);
-                let mut sub_node = node.add(base, range, TreeLeaf::nothing()).subtree();
+                let start = data.offset();
+                let sub_node = node.add(base, 0..0, TreeLeaf::nothing());
                 let variant = tag.get_variant();
-                show_inner(data, space, encoding, variant, &mut sub_node)?;
+                if let &Encoding::Unit = encoding {
+                    // the variant has no value, the tag itself is the value
+                    sub_node.add(variant, intersect(space, start..start), TreeLeaf::nothing());
+                } else {
+                    let result = show_inner(data, space, encoding, variant, sub_node);
+                    sub_node.set_range(intersect(space, start..data.offset()));
+                    result?;
+                }
             } else {
                 return Err(DecodingError::TagNotFound);
             }
@@ -251,55 +330,33 @@ where
             }
         },
         &Encoding::Enum => show_inner(data, space, &Encoding::Uint32, base, node)?,
-        &Encoding::Option(ref encoding) | &Encoding::OptionalField(ref encoding) => {
-            match data.get_u8()? {
-                0 => (),
-                1 => show_inner(data, space, encoding, base, node)?,
-                _ => return Err(DecodingError::UnexpectedOptionDiscriminant),
-            }
+        &Encoding::Option(ref encoding) => match data.get_u8()? {
+            0 => (),
+            1 => show_inner(data, space, encoding, base, node)?,
+            _ => return Err(DecodingError::UnexpectedOptionDiscriminant),
+        },
+        // the optional field of the object is present if prefixed by 0xff, not by 1
+        &Encoding::OptionalField(ref encoding) => match data.get_u8()? {
+            0 => (),
+            0xff => show_inner(data, space, encoding, base, node)?,
+            _ => return Err(DecodingError::UnexpectedOptionDiscriminant),
         },
         &Encoding::Obj(ref fields) => {
             if fields.len() == 1 && fields[0].get_name() == "messages" {
                 show_inner(data, space, &fields[0].get_encoding(), base, node)?;
             } else {
-                let size = estimate_size(data, &Encoding::Obj(fields.clone()))?;
-                let item = data.following(size);
-                let range = intersect(space, item);
-                let mut sub_node = node.add(base, range, TreeLeaf::nothing()).subtree();
-                for field in fields {
-                    if field.get_name() == "operation_hashes_path" {
-                        let mut item = data.following(0);
-                        let mut path = Vec::new();
-                        data.read_path(&mut path)?;
-                        item.end = data.offset();
-                        let range = intersect(space, item);
-                        let mut p = sub_node
-                            .add(field.get_name(), range, TreeLeaf::nothing())
-                            .subtree();
-                        for component in path.into_iter().rev() {
-                            p.add("path_component", 0..0, TreeLeaf::Display(component));
-                        }
-                    } else {
-                        show_inner(
-                            data,
-                            space,
-                            field.get_encoding(),
-                            field.get_name(),
-                            &mut sub_node,
-                        )?;
-                    }
-                }
+                show_object_inner(data, space, fields, base, node)?;
             }
         },
         &Encoding::Tup(ref encodings) => {
-            let size = estimate_size(data, &Encoding::Tup(encodings.clone()))?;
-            let item = data.following(size);
-            let range = intersect(space, item);
-            let mut sub_node = node.add(base, range, TreeLeaf::nothing()).subtree();
-            for (i, encoding) in encodings.iter().enumerate() {
+            let start = data.offset();
+            let sub_node = node.add(base, 0..0, TreeLeaf::nothing());
+            let result = encodings.iter().enumerate().try_for_each(|(i, encoding)| {
                 let n = format!("{}", i);
-                show_inner(data, space, encoding, &n, &mut sub_node)?;
-            }
+                show_inner(data, space, encoding, &n, sub_node)
+            });
+            sub_node.set_range(intersect(space, start..data.offset()));
+            result?;
         },
         &Encoding::Dynamic(ref encoding) => {
             // TODO: use item, highlight the length
@@ -323,8 +380,12 @@ where
         },
         &Encoding::Hash(ref hash_type) => {
             let item = data.following(hash_type.size());
-            let string = hex::encode(data.copy_to_vec(item.len())?);
-            node.add(base, intersect(space, item), TreeLeaf::Display(string));
+            let bytes = data.copy_to_vec(item.len())?;
+            let range = intersect(space, item);
+            // show base58check, like the node and the client do, and hex as well
+            let string = hash_type.bytes_to_string(&bytes);
+            let sub_node = node.add(base, range.clone(), TreeLeaf::Display(string));
+            sub_node.add("hex", range, TreeLeaf::bytes(bytes));
         },
         &Encoding::Split(ref f) => {
             show_inner(data, space, &f(SchemaType::Binary), base, node)?;
@@ -332,28 +393,252 @@ where
         &Encoding::Timestamp => {
             let item = data.following(8);
             let value = data.get_i64()?;
-            let time = NaiveDateTime::from_timestamp(value, 0);
-            node.add(base, intersect(space, item), TreeLeaf::Display(time));
-        },
-        &Encoding::Lazy(ref _f) => {
-            panic!("should not happen");
+            node.add(base, intersect(space, item), TreeLeaf::time(value));
+        },
+        &Encoding::Lazy(ref f) => {
+            if data.is_lazy_nested() {
+                // the recursive schema has no end, the fields are registered
+                // only until the recursion point, decode the rest aside and show it as hex
+                let mut hex = data.clone();
+                let start = data.offset();
+                data.push_lazy()?;
+                let result = show_inner(data, space, &f(), base, &mut Decoded::root());
+                data.pop_lazy();
+                result?;
+                let bytes = hex.copy_to_vec(data.distance(start, data.offset()))?;
+                let item = start..data.offset();
+                node.add(base, intersect(space, item), TreeLeaf::bytes(bytes));
+            } else {
+                data.push_lazy()?;
+                let result = show_inner(data, space, &f(), base, node);
+                data.pop_lazy();
+                result?;
+            }
         },
     };
     Ok(())
 }
 
-fn estimate_size<'a, C>(
-    s: &ChunkedDataInner<'a, C>,
+/// Show the object, and return its node, so the caller can add more leaves in it.
+pub fn show_object<'a, 'n, C>(
+    data: &mut ChunkedData<'a, C>,
+    space: &Range<usize>,
+    fields: &[Field],
+    base: &str,
+    node: &'n mut Decoded,
+) -> Result<&'n mut Decoded, DecodingError>
+where
+    C: HasBodyRange + Clone,
+{
+    let data = data.inner_mut();
+    let start = data.offset();
+    show_object_inner(data, space, fields, base, node).map_err(|e| locate(data, start, e))
+}
+
+fn show_object_inner<'a, 'n, C>(
+    data: &mut ChunkedDataInner<'a, C>,
+    space: &Range<usize>,
+    fields: &[Field],
+    base: &str,
+    node: &'n mut Decoded,
+) -> Result<&'n mut Decoded, DecodingError>
+where
+    C: HasBodyRange + Clone,
+{
+    // the range of the object is known only after all fields are decoded
+    let start = data.offset();
+    let sub_node = node.add(base, 0..0, TreeLeaf::nothing());
+    let result = show_fields(data, space, fields, sub_node);
+    sub_node.set_range(intersect(space, start..data.offset()));
+    result.map(|()| sub_node).map_err(|e| e.within(base))
+}
+
+fn show_fields<'a, C>(
+    data: &mut ChunkedDataInner<'a, C>,
+    space: &Range<usize>,
+    fields: &[Field],
+    sub_node: &mut Decoded,
+) -> Result<(), DecodingError>
+where
+    C: HasBodyRange + Clone,
+{
+    for field in fields {
+        if field.get_name() == "operation_hashes_path" {
+            let position = data.offset();
+            show_path(data, space, field.get_name(), sub_node)
+                .map_err(|e| e.at(position, "path").within(field.get_name()))?;
+        } else if field.get_name() == ENTRYPOINT_NAME {
+            let position = data.offset();
+            show_entrypoint(data, space, field.get_name(), sub_node)
+                .map_err(|e| e.at(position, "string").within(field.get_name()))?;
+        } else if field.get_name() == "data" && is_operation(fields) {
+            show_operation_data(data, space, field.get_encoding(), sub_node)?;
+        } else if field.get_name() == "protocol_data" {
+            show_protocol_data(data, space, field.get_encoding(), sub_node)?;
+        } else if is_micheline(fields, field.get_name()) {
+            let (position, name) = (data.offset(), field.get_name());
+            show_micheline(data, space, field.get_encoding(), name, sub_node)
+                .map_err(|e| e.at(position, "micheline").within(name))?;
+        } else {
+            show_inner(
+                data,
+                space,
+                field.get_encoding(),
+                field.get_name(),
+                sub_node,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn show_path<'a, C>(
+    data: &mut ChunkedDataInner<'a, C>,
+    space: &Range<usize>,
+    name: &str,
+    node: &mut Decoded,
+) -> Result<(), DecodingError>
+where
+    C: HasBodyRange + Clone,
+{
+    let mut item = data.following(0);
+    let mut path = Vec::new();
+    data.read_path(&mut path)?;
+    item.end = data.offset();
+    let range = intersect(space, item);
+    let p = node.add(name, range, TreeLeaf::nothing());
+    for component in path.into_iter().rev() {
+        p.add("path_component", 0..0, TreeLeaf::Display(component));
+    }
+    Ok(())
+}
+
+/// The string with the single byte length.
+fn show_entrypoint<'a, C>(
+    data: &mut ChunkedDataInner<'a, C>,
+    space: &Range<usize>,
+    name: &str,
+    node: &mut Decoded,
+) -> Result<(), DecodingError>
+where
+    C: HasBodyRange + Clone,
+{
+    let mut item = data.following(1);
+    let length = data.get_u8()? as usize;
+    let string = String::from_utf8(data.copy_to_vec(length)?).ok();
+    item.end = data.offset();
+    if let Some(s) = string {
+        node.add(name, intersect(space, item), TreeLeaf::Display(s));
+    }
+    Ok(())
+}
+
+/// Show the operation contents and the signature instead of the opaque data,
+/// if the protocol is known and the data fits the encoding of the contents.
+fn show_operation_data<'a, C>(
+    data: &mut ChunkedDataInner<'a, C>,
+    space: &Range<usize>,
+    encoding: &Encoding,
+    node: &mut Decoded,
+) -> Result<(), DecodingError>
+where
+    C: HasBodyRange + Clone,
+{
+    if let (Some(protocol), Some(length)) =
+        (data.protocol(), data.remaining().checked_sub(SIGNATURE_SIZE))
+    {
+        // decode the contents aside, the wrong protocol usually fails, then show the data
+        let contents = Encoding::list(protocol.contents_encoding());
+        let mut probe = data.clone();
+        let mut decoded = Decoded::root();
+        probe.push_limit(length);
+        if show_inner(&mut probe, space, &contents, "contents", &mut decoded).is_ok() {
+            probe.pop_limit();
+            *data = probe;
+            node.append(decoded);
+            let signature = Encoding::sized(SIGNATURE_SIZE, Encoding::Bytes);
+            return show_inner(data, space, &signature, "signature", node);
+        }
+    }
+    show_inner(data, space, encoding, "data", node)
+}
+
+/// Show the protocol specific part of the block header as hex,
+/// and decode it in the subtree, if the protocol is known and the data fits.
+fn show_protocol_data<'a, C>(
+    data: &mut ChunkedDataInner<'a, C>,
+    space: &Range<usize>,
+    encoding: &Encoding,
+    node: &mut Decoded,
+) -> Result<(), DecodingError>
+where
+    C: HasBodyRange + Clone,
+{
+    if let Some(fields) = data.protocol().map(|p| p.protocol_data_fields()) {
+        // decode the fields aside, the whole protocol data should be decoded,
+        // otherwise the protocol is wrong, then show the data as hex only
+        let mut probe = data.clone();
+        let mut decoded = Decoded::root();
+        let result = fields.iter().try_for_each(|field| {
+            let name = field.get_name();
+            show_inner(&mut probe, space, field.get_encoding(), name, &mut decoded)
+        });
+        if result.is_ok() && probe.remaining() == 0 {
+            let size = data.remaining();
+            let item = data.following(size);
+            let bytes = data.copy_to_vec(size)?;
+            let range = intersect(space, item);
+            node.add("protocol_data", range, TreeLeaf::bytes(bytes))
+                .append(decoded);
+            return Ok(());
+        }
+    }
+    show_inner(data, space, encoding, "protocol_data", node)
+}
+
+/// Show the Micheline expression, or hex if it is malformed.
+fn show_micheline<'a, C>(
+    data: &mut ChunkedDataInner<'a, C>,
+    space: &Range<usize>,
+    encoding: &Encoding,
+    name: &str,
+    node: &mut Decoded,
+) -> Result<(), DecodingError>
+where
+    C: HasBodyRange + Clone,
+{
+    if let &Encoding::Dynamic(_) = encoding {
+        let mut probe = data.clone();
+        if let Ok(expression) = Expression::read_dynamic(&mut probe) {
+            *data = probe;
+            expression.show(node, space, name, MICHELINE_DEPTH);
+            return Ok(());
+        }
+        // the field is the string, so the malformed expression is shown as hex string
+        let mut item = data.following(4);
+        let length = data.get_u32()? as usize;
+        let string = hex::encode(data.copy_to_vec(length)?);
+        item.end = data.offset();
+        node.add(name, intersect(space, item), TreeLeaf::Display(string));
+        return Ok(());
+    }
+    show_inner(data, space, encoding, name, node)
+}
+
+/// Estimate the size of the data without decoding it, the decoding does not need it,
+/// the function is kept to compare with the decoding in the benchmark.
+#[cfg(feature = "bench")]
+pub fn estimate_size<'a, C>(
+    data: &mut ChunkedData<'a, C>,
     encoding: &Encoding,
 ) -> Result<usize, DecodingError>
 where
     C: HasBodyRange + Clone,
 {
-    estimate_size_inner(&mut s.clone(), encoding)
+    estimate_size_inner(&mut data.inner_mut().clone(), encoding)
 }
 
-// TODO: it is double work, optimize it out
-// we should store decoded data and show it only when whole node is collected
+#[cfg(feature = "bench")]
 fn estimate_size_inner<'a, C>(
     data: &mut ChunkedDataInner<'a, C>,
     encoding: &Encoding,
@@ -367,7 +652,7 @@ where
         &Encoding::Int16 | &Encoding::Uint16 => data.advance(2),
         &Encoding::Int31 | &Encoding::Int32 | &Encoding::Uint32 => data.advance(4),
         &Encoding::Int64 => data.advance(8),
-        &Encoding::RangedInt => unimplemented!(),
+        &Encoding::RangedInt => Err(DecodingError::NotSupported("ranged int")),
         &Encoding::Z => {
             let start = data.offset();
             let _ = data.read_z()?;
@@ -378,8 +663,7 @@ where
             let _ = data.read_mutez()?;
             Ok(data.offset() - start)
         },
-        &Encoding::Float => data.advance(8),
-        &Encoding::RangedFloat => unimplemented!(),
+        &Encoding::Float | &Encoding::RangedFloat => data.advance(8),
         &Encoding::Bool => data.advance(1),
         &Encoding::String => {
             let l = data.get_u32()? as usize;
@@ -409,12 +693,15 @@ where
             data.advance(l)
         },
         &Encoding::Enum => estimate_size_inner(data, &Encoding::Uint32),
-        &Encoding::Option(ref encoding) | &Encoding::OptionalField(ref encoding) => {
-            match data.get_u8()? {
-                0 => Ok(1),
-                1 => estimate_size_inner(data, encoding).map(|s| s + 1),
-                _ => Err(DecodingError::UnexpectedOptionDiscriminant),
-            }
+        &Encoding::Option(ref encoding) => match data.get_u8()? {
+            0 => Ok(1),
+            1 => estimate_size_inner(data, encoding).map(|s| s + 1),
+            _ => Err(DecodingError::UnexpectedOptionDiscriminant),
+        },
+        &Encoding::OptionalField(ref encoding) => match data.get_u8()? {
+            0 => Ok(1),
+            0xff => estimate_size_inner(data, encoding).map(|s| s + 1),
+            _ => Err(DecodingError::UnexpectedOptionDiscriminant),
         },
         &Encoding::Tup(ref encodings) => encodings
             .iter()
@@ -427,6 +714,9 @@ where
                     let start = data.offset();
                     data.read_path(&mut Vec::new())?;
                     Ok(data.offset() - start)
+                } else if f.get_name() == ENTRYPOINT_NAME {
+                    let l = data.get_u8()? as usize;
+                    data.advance(l).map(|l| l + 1)
                 } else {
                     estimate_size_inner(data, f.get_encoding())
                 }
@@ -444,53 +734,122 @@ where
         &Encoding::Hash(ref hash_type) => data.advance(hash_type.size()),
         &Encoding::Timestamp => data.advance(8),
         &Encoding::Split(ref f) => estimate_size_inner(data, &f(SchemaType::Binary)),
-        &Encoding::Lazy(ref _f) => panic!("should not happen"),
+        &Encoding::Lazy(ref f) => {
+            data.push_lazy()?;
+            let size = estimate_size_inner(data, &f());
+            data.pop_lazy();
+            size
+        },
     }
 }
 
-fn reverse(s: &BitVec) -> BitVec {
-    let mut reversed = BitVec::new();
-    for bit in s.iter().rev() {
-        reversed.push(bit)
+#[cfg(test)]
+mod tests {
+    use num_bigint::{BigInt, BigUint};
+    use std::sync::Arc;
+    use tezos_encoding::encoding::{Encoding, Field};
+    use super::{
+        super::{ChunkedData, Decoded, Plain},
+        show, TezosReader,
+    };
+
+    #[test]
+    fn z_and_mutez() {
+        let data = [
+            0xc1, 0x01, 0xc0, 0x84, 0x3d, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80,
+            0x80, 0x80, 0x80, 0x02,
+        ];
+        let chunks = [Plain(0..data.len())];
+        let mut data = ChunkedData::new(data.as_ref(), chunks.as_ref(), 0).unwrap();
+        let data = data.inner_mut();
+        assert_eq!(data.read_z().unwrap(), BigInt::from(-65));
+        assert_eq!(data.read_mutez().unwrap(), BigUint::from(1_000_000u32));
+        assert_eq!(data.read_z().unwrap(), BigInt::from(1u8) << 70);
+        assert_eq!(data.remaining(), 0);
     }
-    reversed
-}
 
-fn trim_left(s: &BitVec) -> BitVec {
-    let mut trimmed: BitVec = BitVec::new();
+    #[test]
+    fn error_location() {
+        let encoding = Encoding::Obj(vec![
+            Field::new("a", Encoding::Int32),
+            Field::new("b", Encoding::Obj(vec![Field::new("c", Encoding::Int32)])),
+        ]);
+        let data = [0; 6];
+        let chunks = [Plain(0..data.len())];
+        let mut data = ChunkedData::new(data.as_ref(), chunks.as_ref(), 0).unwrap();
+        let mut decoded = Decoded::root();
+        let error = show(&mut data, &(0..6), &encoding, "message", &mut decoded).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Not enough bytes, field: message.b.c, encoding: int32, byte 4 of the message"
+        );
+        assert_eq!(error.position(), Some(4));
+    }
 
-    let mut notrim = false;
-    for bit in s.iter() {
-        if bit {
-            trimmed.push(bit);
-            notrim = true;
-        } else if notrim {
-            trimmed.push(bit);
-        }
+    #[test]
+    fn optional_field() {
+        let encoding = Encoding::Obj(vec![
+            Field::new("a", Encoding::OptionalField(Box::new(Encoding::Int32))),
+            Field::new("b", Encoding::Option(Box::new(Encoding::Uint8))),
+            Field::new("c", Encoding::OptionalField(Box::new(Encoding::Uint8))),
+        ]);
+        // the optional field is prefixed by 0xff, the option by 1
+        let data = [0xff, 0, 0, 0, 5, 1, 7, 0];
+        let chunks = [Plain(0..data.len())];
+        let mut data = ChunkedData::new(data.as_ref(), chunks.as_ref(), 0).unwrap();
+        let mut decoded = Decoded::root();
+        show(&mut data, &(0..8), &encoding, "message", &mut decoded).unwrap();
+        let names = decoded.children()[0]
+            .children()
+            .iter()
+            .map(Decoded::name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(data.inner_mut().remaining(), 0);
     }
-    trimmed
-}
 
-fn to_byte_vec(s: &BitVec) -> Vec<u8> {
-    let mut bytes = vec![];
-    let mut byte = 0;
-    let mut offset = 0;
-    for (idx_bit, bit) in s.iter().rev().enumerate() {
-        let idx_byte = (idx_bit % 8) as u8;
-        if bit {
-            byte |= 1 << idx_byte;
-        } else {
-            byte &= !(1 << idx_byte);
-        }
-        if idx_byte == 7 {
-            bytes.push(byte);
-            byte = 0;
-        }
-        offset = idx_byte;
+    #[test]
+    fn micheline_only_in_script() {
+        // the field is named as the expression of the parameters, but it is not one
+        let encoding = Encoding::Obj(vec![Field::new("value", Encoding::Uint8)]);
+        let data = [7];
+        let chunks = [Plain(0..data.len())];
+        let mut data = ChunkedData::new(data.as_ref(), chunks.as_ref(), 0).unwrap();
+        let mut decoded = Decoded::root();
+        show(&mut data, &(0..1), &encoding, "message", &mut decoded).unwrap();
+        assert_eq!(decoded.children()[0].children()[0].name(), "value");
+        assert_eq!(data.inner_mut().remaining(), 0);
     }
-    if offset != 7 {
-        bytes.push(byte);
+
+    #[test]
+    fn ranged_int_not_supported() {
+        let data = [0; 4];
+        let chunks = [Plain(0..data.len())];
+        let mut data = ChunkedData::new(data.as_ref(), chunks.as_ref(), 0).unwrap();
+        let mut decoded = Decoded::root();
+        let encoding = Encoding::RangedInt;
+        let error = show(&mut data, &(0..4), &encoding, "message", &mut decoded).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Encoding not supported: ranged int, field: message, encoding: ranged int, \
+             byte 0 of the message"
+        );
+    }
+
+    #[test]
+    fn recursive_lazy() {
+        // the lazy encoding that does not consume data
+        fn recursive() -> Encoding {
+            Encoding::Lazy(Arc::new(recursive))
+        }
+
+        let data = [0; 4];
+        let chunks = [Plain(0..data.len())];
+        let mut data = ChunkedData::new(data.as_ref(), chunks.as_ref(), 0).unwrap();
+        let mut decoded = Decoded::root();
+        let error = show(&mut data, &(0..4), &recursive(), "message", &mut decoded).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Recursive encoding does not consume data"));
     }
-    bytes.reverse();
-    bytes
 }
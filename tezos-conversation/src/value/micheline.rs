@@ -0,0 +1,318 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{fmt, ops::Range};
+use num_bigint::BigInt;
+use wireshark_definitions::TreeLeaf;
+use crate::range_tool::intersect;
+use super::{
+    chunked_data::{ChunkedData, ChunkedDataInner, DecodingError},
+    decoded::Decoded,
+    message::TezosReader,
+    HasBodyRange,
+};
+
+/// The depth of the expression tree that has the fields registered,
+/// the deeper expressions are shown only as the part of the string.
+pub const MICHELINE_DEPTH: usize = 8;
+
+/// The expression deeper than this is considered malformed,
+/// protects the decoder from the stack overflow.
+const MAX_DEPTH: usize = 0x100;
+
+/// The primitives of the protocols 005, 006 and 007 in order of their binary tags.
+const PRIMITIVES: &[&str] = &[
+    "parameter", "storage", "code", "False", "Elt", "Left", "None", "Pair", "Right", "Some",
+    "True", "Unit", "PACK", "UNPACK", "BLAKE2B", "SHA256", "SHA512", "ABS", "ADD", "AMOUNT",
+    "AND", "BALANCE", "CAR", "CDR", "CHECK_SIGNATURE", "COMPARE", "CONCAT", "CONS",
+    "CREATE_ACCOUNT", "CREATE_CONTRACT", "IMPLICIT_ACCOUNT", "DIP", "DROP", "DUP", "EDIV",
+    "EMPTY_MAP", "EMPTY_SET", "EQ", "EXEC", "FAILWITH", "GE", "GET", "GT", "HASH_KEY", "IF",
+    "IF_CONS", "IF_LEFT", "IF_NONE", "INT", "LAMBDA", "LE", "LEFT", "LOOP", "LSL", "LSR", "LT",
+    "MAP", "MEM", "MUL", "NEG", "NEQ", "NIL", "NONE", "NOT", "NOW", "OR", "PAIR", "PUSH",
+    "RIGHT", "SIZE", "SOME", "SOURCE", "SENDER", "SELF", "STEPS_TO_QUOTA", "SUB", "SWAP",
+    "TRANSFER_TOKENS", "SET_DELEGATE", "UNIT", "UPDATE", "XOR", "ITER", "LOOP_LEFT", "ADDRESS",
+    "CONTRACT", "ISNAT", "CAST", "RENAME", "bool", "contract", "int", "key", "key_hash",
+    "lambda", "list", "map", "big_map", "nat", "option", "or", "pair", "set", "signature",
+    "string", "bytes", "mutez", "timestamp", "unit", "operation", "address", "SLICE", "DIG",
+    "DUG", "EMPTY_BIG_MAP", "APPLY", "chain_id", "CHAIN_ID",
+];
+
+/// The decoded expression together with its range in the data.
+pub struct Expression {
+    pub range: Range<usize>,
+    pub node: Node,
+}
+
+pub enum Node {
+    Int(BigInt),
+    String(String),
+    Bytes(Vec<u8>),
+    Prim {
+        prim: &'static str,
+        args: Vec<Expression>,
+        annots: Vec<String>,
+    },
+    Seq(Vec<Expression>),
+}
+
+impl Expression {
+    /// Read the expression prefixed by its length in bytes.
+    pub fn read_dynamic<'a, C>(data: &mut ChunkedDataInner<'a, C>) -> Result<Self, DecodingError>
+    where
+        ChunkedData<'a, C>: Clone,
+        C: HasBodyRange,
+    {
+        let length = data.get_u32()? as usize;
+        if !data.has(length) {
+            return Err(DecodingError::NotEnoughData);
+        }
+        data.push_limit(length);
+        let expression = Self::read(data, 0);
+        let remaining = data.remaining();
+        data.pop_limit();
+        match expression {
+            Ok(_) if remaining != 0 => Err(DecodingError::BadMicheline("trailing data")),
+            expression => expression,
+        }
+    }
+
+    fn read<'a, C>(data: &mut ChunkedDataInner<'a, C>, depth: usize) -> Result<Self, DecodingError>
+    where
+        ChunkedData<'a, C>: Clone,
+        C: HasBodyRange,
+    {
+        fn string<'a, C>(data: &mut ChunkedDataInner<'a, C>) -> Result<String, DecodingError>
+        where
+            C: HasBodyRange,
+        {
+            let length = data.get_u32()? as usize;
+            String::from_utf8(data.copy_to_vec(length)?)
+                .map_err(|_| DecodingError::BadMicheline("string is not utf8"))
+        }
+
+        fn annots<'a, C>(data: &mut ChunkedDataInner<'a, C>) -> Result<Vec<String>, DecodingError>
+        where
+            C: HasBodyRange,
+        {
+            Ok(string(data)?.split_whitespace().map(str::to_owned).collect())
+        }
+
+        fn prim<'a, C>(data: &mut ChunkedDataInner<'a, C>) -> Result<&'static str, DecodingError>
+        where
+            C: HasBodyRange,
+        {
+            PRIMITIVES
+                .get(data.get_u8()? as usize)
+                .cloned()
+                .ok_or(DecodingError::BadMicheline("unknown primitive"))
+        }
+
+        if depth > MAX_DEPTH {
+            return Err(DecodingError::BadMicheline("too deep"));
+        }
+
+        let start = data.offset();
+        let node = match data.get_u8()? {
+            0x00 => Node::Int(data.read_z()?),
+            0x01 => Node::String(string(data)?),
+            0x02 => Node::Seq(Self::read_list(data, depth)?),
+            tag @ 0x03..=0x08 => {
+                // the number of arguments and if there are annotations are in the tag
+                let prim = prim(data)?;
+                let args = (0..((tag - 0x03) / 2))
+                    .map(|_| Self::read(data, depth + 1))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let annots = if tag % 2 == 0 {
+                    annots(data)?
+                } else {
+                    Vec::new()
+                };
+                Node::Prim { prim, args, annots }
+            },
+            0x09 => {
+                let prim = prim(data)?;
+                let args = Self::read_list(data, depth)?;
+                let annots = annots(data)?;
+                Node::Prim { prim, args, annots }
+            },
+            0x0a => {
+                let length = data.get_u32()? as usize;
+                Node::Bytes(data.copy_to_vec(length)?)
+            },
+            _ => return Err(DecodingError::BadMicheline("unknown tag")),
+        };
+
+        Ok(Expression {
+            range: start..data.offset(),
+            node,
+        })
+    }
+
+    /// Read the expressions prefixed by their total length in bytes.
+    fn read_list<'a, C>(
+        data: &mut ChunkedDataInner<'a, C>,
+        depth: usize,
+    ) -> Result<Vec<Self>, DecodingError>
+    where
+        ChunkedData<'a, C>: Clone,
+        C: HasBodyRange,
+    {
+        let length = data.get_u32()? as usize;
+        if !data.has(length) {
+            return Err(DecodingError::NotEnoughData);
+        }
+        data.push_limit(length);
+        let mut items = Vec::new();
+        let result = loop {
+            if data.remaining() == 0 {
+                break Ok(items);
+            }
+            match Self::read(data, depth + 1) {
+                Ok(item) => items.push(item),
+                Err(e) => break Err(e),
+            }
+        };
+        data.pop_limit();
+        result
+    }
+}
+
+impl Expression {
+    /// Show the expression as the single line, and its parts in the subtree,
+    /// the nested expressions are shown until the depth reaches zero.
+    pub fn show(&self, node: &mut Decoded, space: &Range<usize>, name: &str, depth: usize) {
+        let range = intersect(space, self.range.clone());
+        let sub_node = node.add(name, range.clone(), TreeLeaf::Display(self));
+        let children = match &self.node {
+            &Node::Int(ref value) => {
+                sub_node.add("int", range, TreeLeaf::Display(value));
+                &[][..]
+            },
+            &Node::String(ref value) => {
+                sub_node.add("string", range, TreeLeaf::Display(value));
+                &[][..]
+            },
+            &Node::Bytes(ref value) => {
+                sub_node.add("bytes", range, TreeLeaf::bytes(value.clone()));
+                &[][..]
+            },
+            &Node::Prim {
+                prim,
+                ref args,
+                ref annots,
+            } => {
+                sub_node.add("prim", range.clone(), TreeLeaf::Display(prim));
+                if !annots.is_empty() {
+                    sub_node.add("annots", range, TreeLeaf::Display(annots.join(" ")));
+                }
+                args.as_slice()
+            },
+            &Node::Seq(ref items) => items.as_slice(),
+        };
+        if depth > 0 {
+            for child in children {
+                child.show(sub_node, space, "expr", depth - 1);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, false)
+    }
+}
+
+impl Expression {
+    /// Write the expression in Michelson syntax on the single line,
+    /// the primitive with arguments or annotations is wrapped in parentheses if nested.
+    fn write(&self, f: &mut fmt::Formatter<'_>, nested: bool) -> fmt::Result {
+        match &self.node {
+            &Node::Int(ref value) => write!(f, "{}", value),
+            &Node::String(ref value) => {
+                write!(f, "\"")?;
+                for c in value.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            },
+            &Node::Bytes(ref value) => write!(f, "0x{}", hex::encode(value)),
+            &Node::Prim {
+                prim,
+                ref args,
+                ref annots,
+            } => {
+                let wrap = nested && !(args.is_empty() && annots.is_empty());
+                if wrap {
+                    write!(f, "(")?;
+                }
+                write!(f, "{}", prim)?;
+                for annot in annots {
+                    write!(f, " {}", annot)?;
+                }
+                for arg in args {
+                    write!(f, " ")?;
+                    arg.write(f, true)?;
+                }
+                if wrap {
+                    write!(f, ")")?;
+                }
+                Ok(())
+            },
+            &Node::Seq(ref items) => {
+                if items.is_empty() {
+                    return write!(f, "{{}}");
+                }
+                write!(f, "{{ ")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, " ; ")?;
+                    }
+                    item.write(f, false)?;
+                }
+                write!(f, " }}")
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{ChunkedData, Plain},
+        Expression,
+    };
+
+    fn decode(bytes: &[u8]) -> String {
+        let mut data = (bytes.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(bytes);
+        let chunks = [Plain(0..data.len())];
+        let mut data = ChunkedData::new(data.as_ref(), chunks.as_ref(), 0).unwrap();
+        Expression::read_dynamic(data.inner_mut()).unwrap().to_string()
+    }
+
+    #[test]
+    fn micheline() {
+        // Pair 1 "a"
+        let pair = [0x07, 0x07, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x01, 0x61];
+        assert_eq!(decode(&pair), "Pair 1 \"a\"");
+
+        // the nested primitive with arguments is wrapped in parentheses
+        let nested = [0x07, 0x07, 0x07, 0x07, 0x00, 0x01, 0x00, 0x02, 0x00, 0x03];
+        assert_eq!(decode(&nested), "Pair (Pair 1 2) 3");
+
+        // { DROP ; NIL %x operation }
+        let seq = [
+            0x02, 0x00, 0x00, 0x00, 0x0c, 0x03, 0x20, 0x06, 0x3d, 0x03, 0x6d, 0x00, 0x00, 0x00,
+            0x02, 0x25, 0x78,
+        ];
+        assert_eq!(decode(&seq), "{ DROP ; NIL %x operation }");
+    }
+}
@@ -2,9 +2,12 @@
 // SPDX-License-Identifier: MIT
 
 mod chunked_data;
+mod decoded;
 mod fields;
 mod message;
+mod micheline;
 mod named;
+mod protocol;
 
 use std::ops::Range;
 
@@ -13,6 +16,29 @@ pub trait HasBodyRange {
     fn set_continuation(&self);
 }
 
+/// The whole data is one plain chunk without the header and the MAC, for the tests.
+#[cfg(test)]
+#[derive(Clone)]
+struct Plain(Range<usize>);
+
+#[cfg(test)]
+impl HasBodyRange for Plain {
+    fn body(&self) -> Range<usize> {
+        self.0.clone()
+    }
+
+    fn set_continuation(&self) {}
+}
+
+/// How deep the lazy encodings might be nested in each other, the fields are registered
+/// until this depth, the deeper data is shown as hex. The schema might create a new function
+/// each time, so the recursive encoding cannot be recognized, the depth is the only limit.
+const LAZY_DEPTH: usize = 16;
+
 pub use self::fields::{TezosEncoded, Named};
 pub use self::chunked_data::{DecodingError, ChunkedData};
-pub use self::message::show;
+pub use self::message::{show, show_object};
+#[cfg(feature = "bench")]
+pub use self::message::estimate_size;
+pub use self::decoded::Decoded;
+pub use self::protocol::Protocol;
@@ -0,0 +1,250 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use tezos_encoding::encoding::{Encoding, Field, Tag, TagMap};
+use crypto::hash::HashType;
+
+/// The size of the signature that follows the operation contents.
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// The protocol encodes these fields as natural numbers, the dissector decodes them
+/// as `Encoding::Mutez` which has the same binary representation, but they are not amounts.
+pub const NATURAL_FIELDS: &[&str] = &["counter", "gas_limit", "storage_limit"];
+
+/// The field of the named entrypoint, it is a string with the single byte length.
+pub const ENTRYPOINT_NAME: &str = "entrypoint_name";
+
+/// The protocols the dissector can decode the operations of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Proto005,
+    Proto006,
+    Proto007,
+}
+
+impl Protocol {
+    /// The encoding of the single operation content, the operation is a list of them
+    /// followed by the signature.
+    pub fn contents_encoding(&self) -> Encoding {
+        match self {
+            // the protocols 006 and 007 did not change the encoding of the operations
+            &Protocol::Proto005 | &Protocol::Proto006 | &Protocol::Proto007 => contents(),
+        }
+    }
+
+    /// The fields of the protocol specific part of the block header.
+    pub fn protocol_data_fields(&self) -> Vec<Field> {
+        match self {
+            &Protocol::Proto005 | &Protocol::Proto006 | &Protocol::Proto007 => vec![
+                Field::new("priority", Encoding::Uint16),
+                Field::new("proof_of_work_nonce", Encoding::sized(8, Encoding::Bytes)),
+                Field::new(
+                    "seed_nonce_hash",
+                    Encoding::OptionalField(Box::new(Encoding::sized(32, Encoding::Bytes))),
+                ),
+                Field::new("signature", Encoding::sized(SIGNATURE_SIZE, Encoding::Bytes)),
+            ],
+        }
+    }
+}
+
+/// The operation is the object that has only the branch and the opaque data.
+pub fn is_operation(fields: &[Field]) -> bool {
+    match fields {
+        [branch, data] => branch.get_name() == "branch" && data.get_name() == "data",
+        _ => false,
+    }
+}
+
+/// The field contains the Micheline expression, it is either the value of the parameters
+/// of the transaction, or the code or the storage of the script of the origination.
+/// The object is recognized by its fields, the same names elsewhere mean something else.
+pub fn is_micheline(fields: &[Field], name: &str) -> bool {
+    match fields {
+        [first, second] => match (first.get_name().as_str(), second.get_name().as_str()) {
+            ("entrypoint", "value") => name == "value",
+            ("code", "storage") => name == "code" || name == "storage",
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn public_key_hash() -> Encoding {
+    Encoding::Tags(
+        1,
+        TagMap::new(&[
+            Tag::new(0, "ed25519", Encoding::Hash(HashType::ContractTz1Hash)),
+            Tag::new(1, "secp256k1", Encoding::Hash(HashType::ContractTz2Hash)),
+            Tag::new(2, "p256", Encoding::Hash(HashType::ContractTz3Hash)),
+        ]),
+    )
+}
+
+fn public_key() -> Encoding {
+    Encoding::Tags(
+        1,
+        TagMap::new(&[
+            Tag::new(0, "ed25519", Encoding::Hash(HashType::PublicKeyEd25519)),
+            Tag::new(1, "secp256k1", Encoding::Hash(HashType::PublicKeySecp256k1)),
+            Tag::new(2, "p256", Encoding::Hash(HashType::PublicKeyP256)),
+        ]),
+    )
+}
+
+fn contract_id() -> Encoding {
+    Encoding::Tags(
+        1,
+        TagMap::new(&[
+            Tag::new(0, "implicit", public_key_hash()),
+            Tag::new(
+                1,
+                "originated",
+                Encoding::Obj(vec![
+                    Field::new("contract", Encoding::Hash(HashType::ContractKt1Hash)),
+                    Field::new("padding", Encoding::Uint8),
+                ]),
+            ),
+        ]),
+    )
+}
+
+fn entrypoint() -> Encoding {
+    Encoding::Tags(
+        1,
+        TagMap::new(&[
+            Tag::new(0, "default", Encoding::Unit),
+            Tag::new(1, "root", Encoding::Unit),
+            Tag::new(2, "do", Encoding::Unit),
+            Tag::new(3, "set_delegate", Encoding::Unit),
+            Tag::new(4, "remove_delegate", Encoding::Unit),
+            Tag::new(
+                255,
+                "named",
+                Encoding::Obj(vec![Field::new(ENTRYPOINT_NAME, Encoding::String)]),
+            ),
+        ]),
+    )
+}
+
+/// The fields every manager operation starts with.
+fn manager(more: Vec<Field>) -> Encoding {
+    let mut fields = vec![
+        Field::new("source", public_key_hash()),
+        Field::new("fee", Encoding::Mutez),
+        Field::new("counter", Encoding::Mutez),
+        Field::new("gas_limit", Encoding::Mutez),
+        Field::new("storage_limit", Encoding::Mutez),
+    ];
+    fields.extend(more);
+    Encoding::Obj(fields)
+}
+
+fn contents() -> Encoding {
+    let inline = || Encoding::dynamic(Encoding::Bytes);
+    let micheline = || Encoding::dynamic(Encoding::Bytes);
+    Encoding::Tags(
+        1,
+        TagMap::new(&[
+            Tag::new(
+                0,
+                "endorsement",
+                Encoding::Obj(vec![Field::new("level", Encoding::Int32)]),
+            ),
+            Tag::new(
+                1,
+                "seed_nonce_revelation",
+                Encoding::Obj(vec![
+                    Field::new("level", Encoding::Int32),
+                    Field::new("nonce", Encoding::sized(32, Encoding::Bytes)),
+                ]),
+            ),
+            Tag::new(
+                2,
+                "double_endorsement_evidence",
+                Encoding::Obj(vec![Field::new("op1", inline()), Field::new("op2", inline())]),
+            ),
+            Tag::new(
+                3,
+                "double_baking_evidence",
+                Encoding::Obj(vec![Field::new("bh1", inline()), Field::new("bh2", inline())]),
+            ),
+            Tag::new(
+                4,
+                "activate_account",
+                Encoding::Obj(vec![
+                    Field::new("pkh", Encoding::Hash(HashType::ContractTz1Hash)),
+                    Field::new("secret", Encoding::sized(20, Encoding::Bytes)),
+                ]),
+            ),
+            Tag::new(
+                5,
+                "proposals",
+                Encoding::Obj(vec![
+                    Field::new("source", public_key_hash()),
+                    Field::new("period", Encoding::Int32),
+                    Field::new(
+                        "proposals",
+                        Encoding::dynamic(Encoding::list(Encoding::Hash(HashType::ProtocolHash))),
+                    ),
+                ]),
+            ),
+            Tag::new(
+                6,
+                "ballot",
+                Encoding::Obj(vec![
+                    Field::new("source", public_key_hash()),
+                    Field::new("period", Encoding::Int32),
+                    Field::new("proposal", Encoding::Hash(HashType::ProtocolHash)),
+                    Field::new("ballot", Encoding::Int8),
+                ]),
+            ),
+            Tag::new(
+                107,
+                "reveal",
+                manager(vec![Field::new("public_key", public_key())]),
+            ),
+            Tag::new(
+                108,
+                "transaction",
+                manager(vec![
+                    Field::new("amount", Encoding::Mutez),
+                    Field::new("destination", contract_id()),
+                    Field::new(
+                        "parameters",
+                        Encoding::OptionalField(Box::new(Encoding::Obj(vec![
+                            Field::new("entrypoint", entrypoint()),
+                            Field::new("value", micheline()),
+                        ]))),
+                    ),
+                ]),
+            ),
+            Tag::new(
+                109,
+                "origination",
+                manager(vec![
+                    Field::new("balance", Encoding::Mutez),
+                    Field::new(
+                        "delegate",
+                        Encoding::OptionalField(Box::new(public_key_hash())),
+                    ),
+                    Field::new(
+                        "script",
+                        Encoding::Obj(vec![
+                            Field::new("code", micheline()),
+                            Field::new("storage", micheline()),
+                        ]),
+                    ),
+                ]),
+            ),
+            Tag::new(
+                110,
+                "delegation",
+                manager(vec![Field::new(
+                    "delegate",
+                    Encoding::OptionalField(Box::new(public_key_hash())),
+                )]),
+            ),
+        ]),
+    )
+}
@@ -1,10 +1,23 @@
-use std::{fmt, ops::Range, net::SocketAddr};
+use std::{
+    fmt,
+    ops::Range,
+    net::{SocketAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
 
 #[derive(Clone, Debug)]
 pub enum FieldDescriptor<'a> {
     Nothing { name: &'a str, abbrev: &'a str },
     String { name: &'a str, abbrev: &'a str },
     Int64Dec { name: &'a str, abbrev: &'a str },
+    Uint64Dec { name: &'a str, abbrev: &'a str },
+    Boolean { name: &'a str, abbrev: &'a str },
+    Bytes { name: &'a str, abbrev: &'a str },
+    Double { name: &'a str, abbrev: &'a str },
+    AbsoluteTime { name: &'a str, abbrev: &'a str },
+    FrameNum { name: &'a str, abbrev: &'a str },
+    Ipv4 { name: &'a str, abbrev: &'a str },
+    Ipv6 { name: &'a str, abbrev: &'a str },
 }
 
 impl<'a> FieldDescriptor<'a> {
@@ -22,6 +35,38 @@ impl<'a> FieldDescriptor<'a> {
                 name: name.to_owned(),
                 abbrev: abbrev.to_owned(),
             },
+            &FieldDescriptor::Uint64Dec { name, abbrev } => FieldDescriptorOwned::Uint64Dec {
+                name: name.to_owned(),
+                abbrev: abbrev.to_owned(),
+            },
+            &FieldDescriptor::Boolean { name, abbrev } => FieldDescriptorOwned::Boolean {
+                name: name.to_owned(),
+                abbrev: abbrev.to_owned(),
+            },
+            &FieldDescriptor::Bytes { name, abbrev } => FieldDescriptorOwned::Bytes {
+                name: name.to_owned(),
+                abbrev: abbrev.to_owned(),
+            },
+            &FieldDescriptor::Double { name, abbrev } => FieldDescriptorOwned::Double {
+                name: name.to_owned(),
+                abbrev: abbrev.to_owned(),
+            },
+            &FieldDescriptor::AbsoluteTime { name, abbrev } => FieldDescriptorOwned::AbsoluteTime {
+                name: name.to_owned(),
+                abbrev: abbrev.to_owned(),
+            },
+            &FieldDescriptor::FrameNum { name, abbrev } => FieldDescriptorOwned::FrameNum {
+                name: name.to_owned(),
+                abbrev: abbrev.to_owned(),
+            },
+            &FieldDescriptor::Ipv4 { name, abbrev } => FieldDescriptorOwned::Ipv4 {
+                name: name.to_owned(),
+                abbrev: abbrev.to_owned(),
+            },
+            &FieldDescriptor::Ipv6 { name, abbrev } => FieldDescriptorOwned::Ipv6 {
+                name: name.to_owned(),
+                abbrev: abbrev.to_owned(),
+            },
         }
     }
 }
@@ -31,6 +76,14 @@ pub enum FieldDescriptorOwned {
     Nothing { name: String, abbrev: String },
     String { name: String, abbrev: String },
     Int64Dec { name: String, abbrev: String },
+    Uint64Dec { name: String, abbrev: String },
+    Boolean { name: String, abbrev: String },
+    Bytes { name: String, abbrev: String },
+    Double { name: String, abbrev: String },
+    AbsoluteTime { name: String, abbrev: String },
+    FrameNum { name: String, abbrev: String },
+    Ipv4 { name: String, abbrev: String },
+    Ipv6 { name: String, abbrev: String },
 }
 
 pub trait HasFields {
@@ -44,6 +97,12 @@ pub trait TreePresenter {
     where
         D: fmt::Display,
         P: AsRef<str>;
+    /// Attach the expert info to the item, the name is the abbrev of the expert info
+    /// declared by the plugin, the message replaces its summary.
+    fn expert<D, P>(&mut self, name: P, message: D)
+    where
+        D: fmt::Display,
+        P: AsRef<str>;
 }
 
 pub enum TreeLeaf<D>
@@ -53,7 +112,15 @@ where
     Nothing,
     Display(D),
     Int64Dec(i64),
+    Uint64Dec(u64),
+    Boolean(bool),
+    Bytes(Vec<u8>),
     Float64(f64),
+    /// Seconds since unix epoch.
+    AbsoluteTime(i64),
+    FrameNum(u64),
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
 }
 
 impl TreeLeaf<String> {
@@ -61,6 +128,22 @@ impl TreeLeaf<String> {
         TreeLeaf::Int64Dec(v)
     }
 
+    pub fn uint(v: u64) -> Self {
+        TreeLeaf::Uint64Dec(v)
+    }
+
+    pub fn boolean(v: bool) -> Self {
+        TreeLeaf::Boolean(v)
+    }
+
+    pub fn bytes(v: Vec<u8>) -> Self {
+        TreeLeaf::Bytes(v)
+    }
+
+    pub fn time(v: i64) -> Self {
+        TreeLeaf::AbsoluteTime(v)
+    }
+
     pub fn float(v: f64) -> Self {
         TreeLeaf::Float64(v)
     }
@@ -100,4 +183,19 @@ pub trait PacketMetadata {
     fn source(&self) -> SocketAddress;
     fn frame_number(&self) -> u64;
     fn visited(&self) -> bool;
+    /// Tcp sequence number of the first byte of the payload.
+    fn seq(&self) -> Option<u32>;
+    /// Tcp acknowledgment number of the segment.
+    fn ack(&self) -> Option<u32>;
+    /// Tcp flags of the segment, the bits are the same as in the tcp header,
+    /// see `TCP_FLAG_*` constants.
+    fn flags(&self) -> Option<u16>;
+    /// The time the frame was captured, since unix epoch.
+    fn timestamp(&self) -> Duration;
 }
+
+pub const TCP_FLAG_FIN: u16 = 0x01;
+pub const TCP_FLAG_SYN: u16 = 0x02;
+pub const TCP_FLAG_RST: u16 = 0x04;
+pub const TCP_FLAG_PSH: u16 = 0x08;
+pub const TCP_FLAG_ACK: u16 = 0x10;
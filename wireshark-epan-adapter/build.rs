@@ -29,6 +29,10 @@ fn main() {
         .header(format!("{}/epan/proto.h", base))
         .header(format!("{}/epan/packet.h", base))
         .header(format!("{}/epan/conversation.h", base))
+        .header(format!("{}/epan/expert.h", base))
+        .header(format!("{}/epan/column-utils.h", base))
+        .header(format!("{}/epan/tap.h", base))
+        .header(format!("{}/epan/stats_tree.h", base))
         .header(format!("{}/epan/tvbuff.h", base))
         .header(format!("{}/epan/tvbuff-int.h", base))
         .header(format!("{}/epan/dissectors/packet-tcp.h", base))
@@ -29,6 +29,8 @@ extern "C" fn plugin_register() {
         DissectorDescriptor {
             display_name: "Simple\0",
             short_name: "simple_tcp\0",
+            // no default port, available only in Decode As
+            tcp_port: 0,
         },
         // name of protocol and filter
         NameDescriptor {
@@ -59,6 +61,9 @@ extern "C" fn plugin_register() {
                 abbrev: "simple_tree_example.foo.bar0.baz1\0",
             },
         ]],
+        // no expert info
+        &[],
+        // no preferences
         &[],
     );
 
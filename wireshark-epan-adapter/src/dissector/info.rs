@@ -1,21 +1,55 @@
-use wireshark_definitions::{PacketMetadata, SocketAddress};
-use std::net::{SocketAddr, IpAddr};
-use crate::sys;
+use wireshark_definitions::{PacketMetadata, SocketAddress, TCP_FLAG_ACK};
+use std::{
+    net::{SocketAddr, IpAddr},
+    os::raw::c_int,
+    time::Duration,
+};
+use crate::{sys, stats::TapRecord};
+use super::packet::SuperDissectorData;
+
+/// The column of the packet list the dissector might fill.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Column {
+    Protocol,
+    Info,
+}
+
+impl Column {
+    fn index(&self) -> i32 {
+        let index = match self {
+            &Column::Protocol => sys::COL_PROTOCOL,
+            &Column::Info => sys::COL_INFO,
+        };
+        index as _
+    }
+}
 
 /// Provides information about the packet.
 pub struct PacketInfo {
     inner: *mut sys::packet_info,
+    data: SuperDissectorData,
+    tap: c_int,
 }
 
 impl PacketInfo {
-    pub(crate) fn new(raw: *mut sys::packet_info) -> Self {
-        PacketInfo { inner: raw }
+    pub(crate) fn new(raw: *mut sys::packet_info, data: SuperDissectorData, tap: c_int) -> Self {
+        PacketInfo {
+            inner: raw,
+            data,
+            tap,
+        }
     }
 
     pub(crate) fn inner(&self) -> &sys::packet_info {
         unsafe { &*self.inner }
     }
 
+    fn tcp(&self) -> Option<&sys::tcpinfo> {
+        match &self.data {
+            &SuperDissectorData::Tcp(info) => unsafe { info.as_ref() },
+        }
+    }
+
     fn fd(&self) -> &sys::frame_data {
         unsafe { &*self.inner().fd }
     }
@@ -29,6 +63,33 @@ impl PacketInfo {
         };
         key as _
     }
+
+    /// The wireshark does not copy the string, so it should be static and null terminated.
+    pub fn set_column(&self, column: Column, value: &'static str) {
+        unsafe { sys::col_set_str(self.inner().cinfo, column.index(), value.as_ptr() as _) }
+    }
+
+    /// The wireshark copies the string.
+    pub fn append_column(&self, column: Column, value: &str) {
+        let value = format!("{}\0", value);
+        unsafe { sys::col_append_str(self.inner().cinfo, column.index(), value.as_ptr() as _) }
+    }
+
+    pub fn clear_column(&self, column: Column) {
+        unsafe { sys::col_clear(self.inner().cinfo, column.index()) }
+    }
+
+    /// Queue the record for the statistics tree of the plugin.
+    /// Each path is the chain of the nodes of the tree, every node on the path
+    /// counts the record and collects the average of the value.
+    pub fn queue_tap(&self, paths: &[&[&str]], value: i32) {
+        // nobody listens, do not bother allocating the record
+        if self.tap < 0 || unsafe { sys::have_tap_listener(self.tap) } == 0 {
+            return;
+        }
+        let record = TapRecord::new(paths, value);
+        unsafe { sys::tap_queue_packet(self.tap, self.inner, record as _) }
+    }
 }
 
 impl PacketMetadata for PacketInfo {
@@ -52,6 +113,29 @@ impl PacketMetadata for PacketInfo {
     fn visited(&self) -> bool {
         self.fd().visited() != 0
     }
+
+    /// It is relative if the tcp dissector is configured to use relative sequence numbers.
+    fn seq(&self) -> Option<u32> {
+        self.tcp().map(|tcp| tcp.seq)
+    }
+
+    /// The tcp dissector stores the acknowledgment number of the segment itself
+    /// in `lastackseq`, it is meaningful only if the segment has the ACK flag.
+    fn ack(&self) -> Option<u32> {
+        self.tcp()
+            .filter(|tcp| tcp.flags & TCP_FLAG_ACK != 0)
+            .map(|tcp| tcp.lastackseq)
+    }
+
+    fn flags(&self) -> Option<u16> {
+        self.tcp().map(|tcp| tcp.flags)
+    }
+
+    /// Absolute time of the frame.
+    fn timestamp(&self) -> Duration {
+        let ts = self.inner().abs_ts;
+        Duration::new(ts.secs as _, ts.nsecs as _)
+    }
 }
 
 fn read_address(addr: sys::address, port: u16) -> SocketAddress {
@@ -1,7 +1,9 @@
 /// Provides information about the packet: source/destination,
-/// if it already visited, number and key of the conversation.
+/// if it already visited, number and key of the conversation,
+/// tcp sequence and acknowledgment numbers, flags and time,
+/// fills the columns of the packet list and queues the records to the tap.
 mod info;
-pub use self::info::PacketInfo;
+pub use self::info::{PacketInfo, Column};
 
 /// Provides packet payload.
 mod packet;
@@ -7,13 +7,12 @@ pub enum SuperDissectorData {
 
 /// The helper provided to dissector.
 pub struct Packet {
-    _data: SuperDissectorData,
     tvb: *mut sys::tvbuff_t,
 }
 
 impl Packet {
-    pub(crate) fn new(data: SuperDissectorData, tvb: *mut sys::tvbuff_t) -> Self {
-        Packet { _data: data, tvb }
+    pub(crate) fn new(tvb: *mut sys::tvbuff_t) -> Self {
+        Packet { tvb }
     }
 
     /// Payload in the packet. The stuff that dissector will parse and present on UI.
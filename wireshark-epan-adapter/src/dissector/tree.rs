@@ -4,8 +4,10 @@ use crate::sys;
 
 struct Common {
     fields: HashMap<String, i32>,
+    experts: HashMap<String, *mut sys::expert_field>,
     ett: i32,
     tvb: *mut sys::tvbuff_t,
+    pinfo: *mut sys::packet_info,
 }
 
 pub struct Tree {
@@ -18,11 +20,19 @@ pub struct Tree {
 impl Tree {
     pub(crate) fn root(
         fields: HashMap<String, i32>,
+        experts: HashMap<String, *mut sys::expert_field>,
         ett: i32,
         tvb: *mut sys::tvbuff_t,
+        pinfo: *mut sys::packet_info,
         root: *mut sys::proto_tree,
     ) -> Self {
-        let common = Common { fields, ett, tvb };
+        let common = Common {
+            fields,
+            experts,
+            ett,
+            tvb,
+            pinfo,
+        };
 
         Tree {
             common: Rc::new(RefCell::new(common)),
@@ -94,9 +104,99 @@ impl TreePresenter for Tree {
                     value,
                 )
             },
-            TreeLeaf::Float64(value) => {
-                let _ = value;
-                unimplemented!()
+            TreeLeaf::Uint64Dec(value) => unsafe {
+                sys::proto_tree_add_uint64(
+                    self.node,
+                    self.common.borrow().fields[&full_path],
+                    self.common.borrow().tvb,
+                    range.start as _,
+                    range.len() as _,
+                    value,
+                )
+            },
+            TreeLeaf::Boolean(value) => unsafe {
+                sys::proto_tree_add_boolean(
+                    self.node,
+                    self.common.borrow().fields[&full_path],
+                    self.common.borrow().tvb,
+                    range.start as _,
+                    range.len() as _,
+                    value as _,
+                )
+            },
+            // the value might be longer than the range, if the field continues in other frame
+            TreeLeaf::Bytes(value) => unsafe {
+                sys::proto_tree_add_bytes_with_length(
+                    self.node,
+                    self.common.borrow().fields[&full_path],
+                    self.common.borrow().tvb,
+                    range.start as _,
+                    range.len() as _,
+                    value.as_ptr(),
+                    value.len() as _,
+                )
+            },
+            TreeLeaf::Float64(value) => unsafe {
+                sys::proto_tree_add_double(
+                    self.node,
+                    self.common.borrow().fields[&full_path],
+                    self.common.borrow().tvb,
+                    range.start as _,
+                    range.len() as _,
+                    value,
+                )
+            },
+            TreeLeaf::AbsoluteTime(value) => {
+                let value = sys::nstime_t {
+                    secs: value as _,
+                    nsecs: 0,
+                };
+                unsafe {
+                    sys::proto_tree_add_time(
+                        self.node,
+                        self.common.borrow().fields[&full_path],
+                        self.common.borrow().tvb,
+                        range.start as _,
+                        range.len() as _,
+                        &value,
+                    )
+                }
+            },
+            TreeLeaf::FrameNum(value) => unsafe {
+                sys::proto_tree_add_uint(
+                    self.node,
+                    self.common.borrow().fields[&full_path],
+                    self.common.borrow().tvb,
+                    range.start as _,
+                    range.len() as _,
+                    value as _,
+                )
+            },
+            // wireshark expects the address in network byte order
+            TreeLeaf::Ipv4(value) => unsafe {
+                sys::proto_tree_add_ipv4(
+                    self.node,
+                    self.common.borrow().fields[&full_path],
+                    self.common.borrow().tvb,
+                    range.start as _,
+                    range.len() as _,
+                    u32::from_ne_bytes(value.octets()),
+                )
+            },
+            TreeLeaf::Ipv6(value) => {
+                let value = sys::ws_in6_addr {
+                    bytes: value.octets(),
+                };
+                unsafe {
+                    sys::proto_tree_add_ipv6(
+                        self.node,
+                        self.common.borrow().fields[&full_path],
+                        self.common.borrow().tvb,
+                        range.start as _,
+                        range.len() as _,
+                        &value,
+                    )
+                }
             },
         };
 
@@ -107,4 +207,22 @@ impl TreePresenter for Tree {
             node,
         }
     }
+
+    fn expert<D, P>(&mut self, name: P, message: D)
+    where
+        D: fmt::Display,
+        P: AsRef<str>,
+    {
+        let common = self.common.borrow();
+        let message = format!("{}\0", message);
+        unsafe {
+            sys::expert_add_info_format(
+                common.pinfo,
+                self.node,
+                common.experts[name.as_ref()],
+                "%s\0".as_ptr() as _,
+                message.as_ptr(),
+            )
+        }
+    }
 }
@@ -0,0 +1,119 @@
+use std::{os::raw::c_int, ptr};
+use crate::sys;
+
+/// Describes the expert info, the dissector declares them when creating the plugin.
+/// The name is the abbrev of the expert info, it should start with the filter name
+/// of the protocol. All strings should be null terminated.
+pub struct ExpertDescriptor<'a> {
+    pub name: &'a str,
+    pub summary: &'a str,
+    pub group: ExpertGroup,
+    pub severity: ExpertSeverity,
+}
+
+/// The group the expert info belongs, the wireshark shows it in the expert info dialog.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExpertGroup {
+    Checksum,
+    Sequence,
+    ResponseCode,
+    RequestCode,
+    Undecoded,
+    Reassemble,
+    Malformed,
+    Debug,
+    Protocol,
+    Security,
+    Comments,
+    Decryption,
+    Assumption,
+    Deprecated,
+}
+
+/// The severity of the expert info, from the least to the most severe.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ExpertSeverity {
+    Comment,
+    Chat,
+    Note,
+    Warning,
+    Error,
+}
+
+impl ExpertGroup {
+    fn value(&self) -> c_int {
+        let value = match self {
+            &ExpertGroup::Checksum => sys::PI_CHECKSUM,
+            &ExpertGroup::Sequence => sys::PI_SEQUENCE,
+            &ExpertGroup::ResponseCode => sys::PI_RESPONSE_CODE,
+            &ExpertGroup::RequestCode => sys::PI_REQUEST_CODE,
+            &ExpertGroup::Undecoded => sys::PI_UNDECODED,
+            &ExpertGroup::Reassemble => sys::PI_REASSEMBLE,
+            &ExpertGroup::Malformed => sys::PI_MALFORMED,
+            &ExpertGroup::Debug => sys::PI_DEBUG,
+            &ExpertGroup::Protocol => sys::PI_PROTOCOL,
+            &ExpertGroup::Security => sys::PI_SECURITY,
+            &ExpertGroup::Comments => sys::PI_COMMENTS_GROUP,
+            &ExpertGroup::Decryption => sys::PI_DECRYPTION,
+            &ExpertGroup::Assumption => sys::PI_ASSUMPTION,
+            &ExpertGroup::Deprecated => sys::PI_DEPRECATED,
+        };
+        value as _
+    }
+}
+
+impl ExpertSeverity {
+    fn value(&self) -> c_int {
+        let value = match self {
+            &ExpertSeverity::Comment => sys::PI_COMMENT,
+            &ExpertSeverity::Chat => sys::PI_CHAT,
+            &ExpertSeverity::Note => sys::PI_NOTE,
+            &ExpertSeverity::Warning => sys::PI_WARN,
+            &ExpertSeverity::Error => sys::PI_ERROR,
+        };
+        value as _
+    }
+}
+
+impl<'a> ExpertDescriptor<'a> {
+    /// The name without the terminating null.
+    pub(crate) fn abbrev(&self) -> String {
+        self.name.trim_end_matches('\0').to_owned()
+    }
+
+    /// The same as `EXPFILL` macro does, the wireshark fills the rest on registration.
+    pub(crate) fn info(&self, handle: &mut sys::expert_field) -> sys::ei_register_info {
+        sys::ei_register_info {
+            ids: handle,
+            eiinfo: sys::expert_field_info {
+                name: self.name.as_ptr() as _,
+                group: self.group.value(),
+                severity: self.severity.value(),
+                summary: self.summary.as_ptr() as _,
+                id: 0,
+                protocol: ptr::null(),
+                orig_severity: 0,
+                hf_info: sys::hf_register_info {
+                    p_id: ptr::null_mut(),
+                    hfinfo: sys::header_field_info {
+                        name: ptr::null(),
+                        abbrev: ptr::null(),
+                        type_: sys::ftenum_FT_NONE,
+                        display: sys::field_display_e_BASE_NONE as _,
+                        strings: ptr::null(),
+                        bitmask: 0,
+                        blurb: ptr::null(),
+                        id: -1,
+                        parent: 0,
+                        ref_type: sys::hf_ref_type_HF_REF_TYPE_NONE,
+                        same_name_prev_id: -1,
+                        same_name_next: ptr::null_mut(),
+                    },
+                },
+            },
+        }
+    }
+}
+
+/// The same as `EI_INIT` macro, the wireshark writes the actual handle on registration.
+pub(crate) const EXPERT_FIELD_INIT: sys::expert_field = sys::expert_field { ei: -1, hf: -1 };
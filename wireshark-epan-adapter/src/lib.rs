@@ -3,10 +3,19 @@ mod sys;
 
 /// Covers plugin, protocol and dissector registering.
 mod plugin;
-pub use self::plugin::{
-    Plugin, NameDescriptor, PrefFilenameDescriptor,
-    DissectorDescriptor, Dissector,
-};
+pub use self::plugin::{Plugin, NameDescriptor, DissectorDescriptor, Dissector};
+
+/// Preferences of the dissector, declaring, registering and reading the values.
+mod preferences;
+pub use self::preferences::{PrefDescriptor, PrefEnumValue, PrefValue, Preferences};
+
+/// Expert info of the dissector, declaring and registering.
+mod expert;
+pub use self::expert::{ExpertDescriptor, ExpertGroup, ExpertSeverity};
+
+/// The tap of the dissector and the statistics tree that counts the tapped records.
+mod stats;
+pub use self::stats::StatsDescriptor;
 
 /// Wrappers around stuff that passed inside the dissector.
 pub mod dissector;
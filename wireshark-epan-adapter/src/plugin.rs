@@ -1,25 +1,46 @@
 use wireshark_definitions::{FieldDescriptor, FieldDescriptorOwned, HasFields};
 use std::{
     collections::HashMap,
-    os::raw::{c_int, c_char, c_void},
+    os::raw::{c_int, c_void},
     cell::RefCell,
     ptr,
 };
 use crate::sys;
-use super::dissector::{Packet, SuperDissectorData, PacketInfo, Tree};
+use super::{
+    dissector::{Packet, SuperDissectorData, PacketInfo, Tree},
+    preferences::{PrefDescriptor, PrefsStorage, Preferences},
+    expert::{ExpertDescriptor, EXPERT_FIELD_INIT},
+    stats::{StatsDescriptor, TapRecord},
+};
 
 /// Should be implemented for dissector.
 pub trait Dissector {
-    /// The only preference supported is filename.
-    /// Called when the user choose some file.
-    fn prefs_update(&mut self, filenames: Vec<&str>) {
-        let _ = filenames;
+    /// Receives current values of all preferences declared by `PrefDescriptor`s.
+    /// Called when the user choose some file or change some value.
+    /// Wireshark redissects the whole capture after the preferences changed,
+    /// the `cleanup` is called before the redissection,
+    /// and the packets are considered not visited.
+    fn prefs_update(&mut self, preferences: &Preferences) {
+        let _ = preferences;
     }
 
     /// Called when a new packet just arrive
     /// or when the user click on some packet in the interface.
     fn consume(&mut self, root: &mut Tree, packet: &Packet, packet_info: &PacketInfo) -> usize;
 
+    /// Called instead of `consume` when the packet goes to the port of the dissector,
+    /// or the user chose the dissector by Decode As.
+    /// The user knows the packet belongs to the protocol, so the dissector should not
+    /// reject it as the heuristic does.
+    fn consume_forced(
+        &mut self,
+        root: &mut Tree,
+        packet: &Packet,
+        packet_info: &PacketInfo,
+    ) -> usize {
+        self.consume(root, packet, packet_info)
+    }
+
     /// Called when capturing session end.
     /// The dissector is not destroyed, it might be used in the next capturing session.
     fn cleanup(&mut self);
@@ -27,12 +48,16 @@ pub trait Dissector {
 
 struct PluginPrivates {
     plugin: sys::proto_plugin,
+    tap_plugin: sys::tap_plugin,
     proto_handle: c_int,
+    tap_handle: c_int,
     field_handles: Vec<c_int>,
     hf: Vec<sys::hf_register_info>,
     ett_handle: c_int,
     ett_info: *mut c_int,
-    pref_filenames: Vec<*const c_char>,
+    expert_handles: Vec<sys::expert_field>,
+    ei: Vec<sys::ei_register_info>,
+    prefs: PrefsStorage,
     callback_registered: bool,
     dissector: Option<Box<dyn Dissector>>,
 }
@@ -43,12 +68,18 @@ impl PluginPrivates {
             register_protoinfo: None,
             register_handoff: None,
         },
+        tap_plugin: sys::tap_plugin {
+            register_tap_listener: None,
+        },
         proto_handle: -1,
+        tap_handle: -1,
         field_handles: Vec::new(),
         hf: Vec::new(),
         ett_handle: -1,
         ett_info: ptr::null_mut(),
-        pref_filenames: Vec::new(),
+        expert_handles: Vec::new(),
+        ei: Vec::new(),
+        prefs: PrefsStorage::EMPTY,
         callback_registered: false,
         dissector: None,
     };
@@ -60,7 +91,9 @@ pub struct Plugin<'a> {
     name_descriptor: NameDescriptor<'a>,
     field_descriptors: &'a [&'a [FieldDescriptor<'a>]],
     field_descriptors_owned: Vec<FieldDescriptorOwned>,
-    filename_descriptors: &'a [PrefFilenameDescriptor<'a>],
+    expert_descriptors: &'a [ExpertDescriptor<'a>],
+    pref_descriptors: &'a [PrefDescriptor<'a>],
+    stats_descriptor: Option<StatsDescriptor<'a>>,
 }
 
 pub struct NameDescriptor<'a> {
@@ -73,61 +106,104 @@ trait Info {
     fn info(&self, handle: &mut c_int) -> sys::hf_register_info;
 }
 
+/// The header field of the given type, the display depends on the type.
+fn register_info(
+    handle: &mut c_int,
+    name: &str,
+    abbrev: &str,
+    type_: sys::ftenum,
+    display: c_int,
+) -> sys::hf_register_info {
+    sys::hf_register_info {
+        p_id: handle,
+        hfinfo: sys::header_field_info {
+            name: name.as_ptr() as _,
+            abbrev: abbrev.as_ptr() as _,
+            type_,
+            display,
+            strings: ptr::null(),
+            bitmask: 0,
+            blurb: ptr::null(),
+            id: -1,
+            parent: 0,
+            ref_type: sys::hf_ref_type_HF_REF_TYPE_NONE,
+            same_name_prev_id: -1,
+            same_name_next: ptr::null_mut(),
+        },
+    }
+}
+
 impl<'a> Info for FieldDescriptor<'a> {
     fn info(&self, handle: &mut c_int) -> sys::hf_register_info {
-        match self {
-            &FieldDescriptor::Nothing { name, abbrev } => sys::hf_register_info {
-                p_id: handle,
-                hfinfo: sys::header_field_info {
-                    name: name.as_ptr() as _,
-                    abbrev: abbrev.as_ptr() as _,
-                    type_: sys::ftenum_FT_NONE,
-                    display: sys::field_display_e_BASE_NONE as _,
-                    strings: ptr::null(),
-                    bitmask: 0,
-                    blurb: ptr::null(),
-                    id: -1,
-                    parent: 0,
-                    ref_type: sys::hf_ref_type_HF_REF_TYPE_NONE,
-                    same_name_prev_id: -1,
-                    same_name_next: ptr::null_mut(),
-                },
-            },
-            &FieldDescriptor::String { name, abbrev } => sys::hf_register_info {
-                p_id: handle,
-                hfinfo: sys::header_field_info {
-                    name: name.as_ptr() as _,
-                    abbrev: abbrev.as_ptr() as _,
-                    type_: sys::ftenum_FT_STRING,
-                    display: sys::field_display_e_BASE_NONE as _,
-                    strings: ptr::null(),
-                    bitmask: 0,
-                    blurb: ptr::null(),
-                    id: -1,
-                    parent: 0,
-                    ref_type: sys::hf_ref_type_HF_REF_TYPE_NONE,
-                    same_name_prev_id: -1,
-                    same_name_next: ptr::null_mut(),
-                },
-            },
-            &FieldDescriptor::Int64Dec { name, abbrev } => sys::hf_register_info {
-                p_id: handle,
-                hfinfo: sys::header_field_info {
-                    name: name.as_ptr() as _,
-                    abbrev: abbrev.as_ptr() as _,
-                    type_: sys::ftenum_FT_INT64,
-                    display: sys::field_display_e_BASE_DEC as _,
-                    strings: ptr::null(),
-                    bitmask: 0,
-                    blurb: ptr::null(),
-                    id: -1,
-                    parent: 0,
-                    ref_type: sys::hf_ref_type_HF_REF_TYPE_NONE,
-                    same_name_prev_id: -1,
-                    same_name_next: ptr::null_mut(),
-                },
-            },
-        }
+        let (name, abbrev, type_, display) = match self {
+            &FieldDescriptor::Nothing { name, abbrev } => (
+                name,
+                abbrev,
+                sys::ftenum_FT_NONE,
+                sys::field_display_e_BASE_NONE,
+            ),
+            &FieldDescriptor::String { name, abbrev } => (
+                name,
+                abbrev,
+                sys::ftenum_FT_STRING,
+                sys::field_display_e_BASE_NONE,
+            ),
+            &FieldDescriptor::Int64Dec { name, abbrev } => (
+                name,
+                abbrev,
+                sys::ftenum_FT_INT64,
+                sys::field_display_e_BASE_DEC,
+            ),
+            &FieldDescriptor::Uint64Dec { name, abbrev } => (
+                name,
+                abbrev,
+                sys::ftenum_FT_UINT64,
+                sys::field_display_e_BASE_DEC,
+            ),
+            &FieldDescriptor::Boolean { name, abbrev } => (
+                name,
+                abbrev,
+                sys::ftenum_FT_BOOLEAN,
+                sys::field_display_e_BASE_NONE,
+            ),
+            &FieldDescriptor::Bytes { name, abbrev } => (
+                name,
+                abbrev,
+                sys::ftenum_FT_BYTES,
+                sys::field_display_e_BASE_NONE,
+            ),
+            &FieldDescriptor::Double { name, abbrev } => (
+                name,
+                abbrev,
+                sys::ftenum_FT_DOUBLE,
+                sys::field_display_e_BASE_NONE,
+            ),
+            &FieldDescriptor::AbsoluteTime { name, abbrev } => (
+                name,
+                abbrev,
+                sys::ftenum_FT_ABSOLUTE_TIME,
+                sys::absolute_time_display_e_ABSOLUTE_TIME_UTC,
+            ),
+            &FieldDescriptor::FrameNum { name, abbrev } => (
+                name,
+                abbrev,
+                sys::ftenum_FT_FRAMENUM,
+                sys::field_display_e_BASE_NONE,
+            ),
+            &FieldDescriptor::Ipv4 { name, abbrev } => (
+                name,
+                abbrev,
+                sys::ftenum_FT_IPv4,
+                sys::field_display_e_BASE_NONE,
+            ),
+            &FieldDescriptor::Ipv6 { name, abbrev } => (
+                name,
+                abbrev,
+                sys::ftenum_FT_IPv6,
+                sys::field_display_e_BASE_NONE,
+            ),
+        };
+        register_info(handle, name, abbrev, type_, display as _)
     }
 }
 
@@ -137,18 +213,47 @@ impl Info for FieldDescriptorOwned {
             &FieldDescriptorOwned::Nothing {
                 ref name,
                 ref abbrev,
-                ..
             } => FieldDescriptor::Nothing { name, abbrev }.info(handle),
             &FieldDescriptorOwned::String {
                 ref name,
                 ref abbrev,
-                ..
             } => FieldDescriptor::String { name, abbrev }.info(handle),
             &FieldDescriptorOwned::Int64Dec {
                 ref name,
                 ref abbrev,
-                ..
             } => FieldDescriptor::Int64Dec { name, abbrev }.info(handle),
+            &FieldDescriptorOwned::Uint64Dec {
+                ref name,
+                ref abbrev,
+            } => FieldDescriptor::Uint64Dec { name, abbrev }.info(handle),
+            &FieldDescriptorOwned::Boolean {
+                ref name,
+                ref abbrev,
+            } => FieldDescriptor::Boolean { name, abbrev }.info(handle),
+            &FieldDescriptorOwned::Bytes {
+                ref name,
+                ref abbrev,
+            } => FieldDescriptor::Bytes { name, abbrev }.info(handle),
+            &FieldDescriptorOwned::Double {
+                ref name,
+                ref abbrev,
+            } => FieldDescriptor::Double { name, abbrev }.info(handle),
+            &FieldDescriptorOwned::AbsoluteTime {
+                ref name,
+                ref abbrev,
+            } => FieldDescriptor::AbsoluteTime { name, abbrev }.info(handle),
+            &FieldDescriptorOwned::FrameNum {
+                ref name,
+                ref abbrev,
+            } => FieldDescriptor::FrameNum { name, abbrev }.info(handle),
+            &FieldDescriptorOwned::Ipv4 {
+                ref name,
+                ref abbrev,
+            } => FieldDescriptor::Ipv4 { name, abbrev }.info(handle),
+            &FieldDescriptorOwned::Ipv6 {
+                ref name,
+                ref abbrev,
+            } => FieldDescriptor::Ipv6 { name, abbrev }.info(handle),
         }
     }
 }
@@ -160,9 +265,17 @@ trait Abbrev {
 impl<'a> Abbrev for FieldDescriptor<'a> {
     fn abbrev(&self) -> String {
         match self {
-            &FieldDescriptor::Nothing { abbrev, .. } => abbrev.to_string(),
-            &FieldDescriptor::String { abbrev, .. } => abbrev.to_string(),
-            &FieldDescriptor::Int64Dec { abbrev, .. } => abbrev.to_string(),
+            &FieldDescriptor::Nothing { abbrev, .. }
+            | &FieldDescriptor::String { abbrev, .. }
+            | &FieldDescriptor::Int64Dec { abbrev, .. }
+            | &FieldDescriptor::Uint64Dec { abbrev, .. }
+            | &FieldDescriptor::Boolean { abbrev, .. }
+            | &FieldDescriptor::Bytes { abbrev, .. }
+            | &FieldDescriptor::Double { abbrev, .. }
+            | &FieldDescriptor::AbsoluteTime { abbrev, .. }
+            | &FieldDescriptor::FrameNum { abbrev, .. }
+            | &FieldDescriptor::Ipv4 { abbrev, .. }
+            | &FieldDescriptor::Ipv6 { abbrev, .. } => abbrev.to_string(),
         }
     }
 }
@@ -170,22 +283,27 @@ impl<'a> Abbrev for FieldDescriptor<'a> {
 impl Abbrev for FieldDescriptorOwned {
     fn abbrev(&self) -> String {
         match self {
-            &FieldDescriptorOwned::Nothing { ref abbrev, .. } => abbrev.to_string(),
-            &FieldDescriptorOwned::String { ref abbrev, .. } => abbrev.clone(),
-            &FieldDescriptorOwned::Int64Dec { ref abbrev, .. } => abbrev.clone(),
+            &FieldDescriptorOwned::Nothing { ref abbrev, .. }
+            | &FieldDescriptorOwned::String { ref abbrev, .. }
+            | &FieldDescriptorOwned::Int64Dec { ref abbrev, .. }
+            | &FieldDescriptorOwned::Uint64Dec { ref abbrev, .. }
+            | &FieldDescriptorOwned::Boolean { ref abbrev, .. }
+            | &FieldDescriptorOwned::Bytes { ref abbrev, .. }
+            | &FieldDescriptorOwned::Double { ref abbrev, .. }
+            | &FieldDescriptorOwned::AbsoluteTime { ref abbrev, .. }
+            | &FieldDescriptorOwned::FrameNum { ref abbrev, .. }
+            | &FieldDescriptorOwned::Ipv4 { ref abbrev, .. }
+            | &FieldDescriptorOwned::Ipv6 { ref abbrev, .. } => abbrev.clone(),
         }
     }
 }
 
-pub struct PrefFilenameDescriptor<'a> {
-    pub name: &'a str,
-    pub title: &'a str,
-    pub description: &'a str,
-}
-
 pub struct DissectorDescriptor<'a> {
     pub display_name: &'a str,
     pub short_name: &'a str,
+    /// The default tcp port of the protocol, the user can change it in the preferences,
+    /// zero means no default port, but the dissector is still available in Decode As.
+    pub tcp_port: u16,
 }
 
 impl<'a> Plugin<'a> {
@@ -193,7 +311,8 @@ impl<'a> Plugin<'a> {
         dissector_descriptor: DissectorDescriptor<'a>,
         name_descriptor: NameDescriptor<'a>,
         field_descriptors: &'a [&'a [FieldDescriptor<'a>]],
-        filename_descriptors: &'a [PrefFilenameDescriptor<'a>],
+        expert_descriptors: &'a [ExpertDescriptor<'a>],
+        pref_descriptors: &'a [PrefDescriptor<'a>],
     ) -> Self {
         Plugin {
             privates: RefCell::new(PluginPrivates::EMPTY),
@@ -201,10 +320,20 @@ impl<'a> Plugin<'a> {
             name_descriptor,
             field_descriptors,
             field_descriptors_owned: Vec::new(),
-            filename_descriptors,
+            expert_descriptors,
+            pref_descriptors,
+            stats_descriptor: None,
         }
     }
 
+    /// The statistics tree counts the records the dissector queues to the tap,
+    /// see `PacketInfo::queue_tap`.
+    pub fn register_stats(self, stats_descriptor: StatsDescriptor<'a>) -> Self {
+        let mut s = self;
+        s.stats_descriptor = Some(stats_descriptor);
+        s
+    }
+
     pub fn register_type<T>(self) -> Self
     where
         T: HasFields,
@@ -240,6 +369,16 @@ impl<'a> Plugin<'a> {
         .chain(it)
         .collect()
     }
+
+    fn experts(&self) -> HashMap<String, *mut sys::expert_field> {
+        let mut state = self.privates.borrow_mut();
+
+        self.expert_descriptors
+            .iter()
+            .map(ExpertDescriptor::abbrev)
+            .zip(state.expert_handles.iter_mut().map(|x| x as *mut _))
+            .collect()
+    }
 }
 
 impl Plugin<'static> {
@@ -270,6 +409,9 @@ impl Plugin<'static> {
                 };
                 let mut state = p.privates.borrow_mut();
                 state.proto_handle = proto;
+                // the tap has the same name as the protocol filter
+                let tap_name = p.name_descriptor.filter_name;
+                state.tap_handle = unsafe { sys::register_tap(tap_name.as_ptr() as _) };
 
                 let mut field_handles = {
                     let len = p.field_descriptors.iter().map(|x| x.len()).sum::<usize>();
@@ -300,42 +442,46 @@ impl Plugin<'static> {
                     sys::proto_register_subtree_array(&state.ett_info as _, 1);
                 }
 
-                extern "C" fn preferences_update_cb() {
-                    use std::ffi::CStr;
+                if !p.expert_descriptors.is_empty() {
+                    let mut expert_handles = vec![EXPERT_FIELD_INIT; p.expert_descriptors.len()];
+                    state.ei = p
+                        .expert_descriptors
+                        .iter()
+                        .zip(expert_handles.iter_mut())
+                        .map(|(descriptor, handle)| descriptor.info(handle))
+                        .collect();
+                    state.expert_handles = expert_handles;
+
+                    unsafe {
+                        let module = sys::expert_register_protocol(proto);
+                        sys::expert_register_field_array(
+                            module,
+                            state.ei.as_mut_ptr(),
+                            state.ei.len() as _,
+                        );
+                    }
+                }
 
+                extern "C" fn preferences_update_cb() {
                     with_plugin(|p| {
                         let mut state = p.privates.borrow_mut();
-                        let filenames = state
-                            .pref_filenames
-                            .iter()
-                            .map(|&p| {
-                                let s = unsafe { CStr::from_ptr(p) };
-                                s.to_str().unwrap()
-                            })
-                            .collect();
-                        state.dissector.as_mut().unwrap().prefs_update(filenames);
+                        let preferences = state.prefs.read(p.pref_descriptors);
+                        state
+                            .dissector
+                            .as_mut()
+                            .unwrap()
+                            .prefs_update(&preferences);
                     })
                 }
 
-                let filename_descriptors = &p.filename_descriptors;
-                if !filename_descriptors.is_empty() {
-                    state
-                        .pref_filenames
-                        .resize(filename_descriptors.len(), ptr::null());
+                if !p.pref_descriptors.is_empty() {
                     let prefs =
                         unsafe { sys::prefs_register_protocol(proto, Some(preferences_update_cb)) };
-                    for (i, d) in filename_descriptors.iter().enumerate() {
-                        unsafe {
-                            sys::prefs_register_filename_preference(
-                                prefs,
-                                d.name.as_ptr() as _,
-                                d.title.as_ptr() as _,
-                                d.description.as_ptr() as _,
-                                state.pref_filenames.get_mut(i).unwrap(),
-                                0,
-                            );
-                        }
+                    // changing the preference requires redissecting the capture
+                    unsafe {
+                        sys::prefs_set_module_effect_flags(prefs, sys::PREF_EFFECT_DISSECTION);
                     }
+                    state.prefs.register(prefs, p.pref_descriptors);
                 }
             })
         }
@@ -352,12 +498,58 @@ impl Plugin<'static> {
                     if let &mut Some(ref mut d) = &mut state.dissector {
                         d.cleanup();
                     }
+                    // returning false unregisters the callback,
+                    // it will be registered again in the next file scope,
+                    // for example, when the capture is redissected
+                    state.callback_registered = false;
                 }),
             }
 
             0
         }
 
+        fn dissect(
+            tvb: *mut sys::tvbuff_t,
+            pinfo: *mut sys::packet_info,
+            tree: *mut sys::proto_tree,
+            data: *mut c_void,
+            forced: bool,
+        ) -> usize {
+            with_plugin(|p| {
+                {
+                    let mut state = p.privates.borrow_mut();
+                    if !state.callback_registered {
+                        unsafe {
+                            sys::wmem_register_callback(
+                                sys::wmem_file_scope(),
+                                Some(wmem_cb),
+                                ptr::null_mut(),
+                            );
+                        }
+                        state.callback_registered = true;
+                    }
+                }
+
+                let fields = p.fields();
+                let experts = p.experts();
+                let packet = Packet::new(tvb);
+                let (ett, tap) = {
+                    let state = p.privates.borrow();
+                    (state.ett_handle, state.tap_handle)
+                };
+                let mut tree = Tree::root(fields, experts, ett, tvb, pinfo, tree);
+                let data = SuperDissectorData::Tcp(data as *mut sys::tcpinfo);
+                let packet_info = PacketInfo::new(pinfo, data, tap);
+                let mut state = p.privates.borrow_mut();
+                let dissector = state.dissector.as_mut().unwrap();
+                if forced {
+                    dissector.consume_forced(&mut tree, &packet, &packet_info)
+                } else {
+                    dissector.consume(&mut tree, &packet, &packet_info)
+                }
+            })
+        }
+
         extern "C" fn register_handoff() {
             extern "C" fn heur_dissector(
                 tvb: *mut sys::tvbuff_t,
@@ -365,31 +557,17 @@ impl Plugin<'static> {
                 tree: *mut sys::proto_tree,
                 data: *mut c_void,
             ) -> sys::gboolean {
-                with_plugin(|p| {
-                    {
-                        let mut state = p.privates.borrow_mut();
-                        if !state.callback_registered {
-                            unsafe {
-                                sys::wmem_register_callback(
-                                    sys::wmem_file_scope(),
-                                    Some(wmem_cb),
-                                    ptr::null_mut(),
-                                );
-                            }
-                            state.callback_registered = true;
-                        }
-                    }
+                dissect(tvb, pinfo, tree, data, false) as _
+            }
 
-                    let fields = p.fields();
-                    let packet =
-                        Packet::new(SuperDissectorData::Tcp(data as *mut sys::tcpinfo), tvb);
-                    let mut tree = Tree::root(fields, p.privates.borrow().ett_handle, tvb, tree);
-                    let packet_info = PacketInfo::new(pinfo);
-                    let mut state = p.privates.borrow_mut();
-                    let dissector = state.dissector.as_mut().unwrap();
-                    let processed_length = dissector.consume(&mut tree, &packet, &packet_info);
-                    processed_length as _
-                })
+            // the tcp dissector calls it for the port of the dissector and for Decode As
+            extern "C" fn port_dissector(
+                tvb: *mut sys::tvbuff_t,
+                pinfo: *mut sys::packet_info,
+                tree: *mut sys::proto_tree,
+                data: *mut c_void,
+            ) -> c_int {
+                dissect(tvb, pinfo, tree, data, true) as _
             }
 
             with_plugin(|p| {
@@ -404,6 +582,46 @@ impl Plugin<'static> {
                         proto_handle,
                         sys::heuristic_enable_e_HEURISTIC_ENABLE,
                     );
+                    // also registers the preference for the port and makes the dissector
+                    // available in Decode As
+                    let handle = sys::create_dissector_handle(Some(port_dissector), proto_handle);
+                    sys::dissector_add_uint_with_preference(
+                        "tcp.port\0".as_ptr() as _,
+                        p.dissector_descriptor.tcp_port as _,
+                        handle,
+                    );
+                }
+            })
+        }
+
+        extern "C" fn register_tap_listener() {
+            // the nodes are created when the records come
+            extern "C" fn stats_init(_st: *mut sys::stats_tree) {}
+
+            extern "C" fn stats_packet(
+                st: *mut sys::stats_tree,
+                _pinfo: *mut sys::packet_info,
+                _edt: *mut sys::epan_dissect_t,
+                record: *const c_void,
+            ) -> sys::tap_packet_status {
+                let record = unsafe { &*(record as *const TapRecord) };
+                record.tick(st);
+                sys::tap_packet_status_TAP_PACKET_REDRAW
+            }
+
+            with_plugin(|p| {
+                if let &Some(ref stats) = &p.stats_descriptor {
+                    unsafe {
+                        sys::stats_tree_register_plugin(
+                            p.name_descriptor.filter_name.as_ptr() as _,
+                            stats.abbrev.as_ptr() as _,
+                            stats.name.as_ptr() as _,
+                            0,
+                            Some(stats_packet),
+                            Some(stats_init),
+                            None,
+                        );
+                    }
                 }
             })
         }
@@ -423,6 +641,14 @@ impl Plugin<'static> {
             unsafe {
                 sys::proto_register_plugin(&state.plugin);
             }
+            if context.as_ref().unwrap().stats_descriptor.is_some() {
+                state.tap_plugin = sys::tap_plugin {
+                    register_tap_listener: Some(register_tap_listener),
+                };
+                unsafe {
+                    sys::tap_register_plugin(&state.tap_plugin);
+                }
+            }
         });
     }
 }
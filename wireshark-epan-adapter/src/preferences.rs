@@ -0,0 +1,347 @@
+use std::{
+    os::raw::c_char,
+    ffi::CStr,
+    ptr,
+};
+use crate::sys;
+
+/// Describes the preference, the dissector declares them when creating the plugin.
+/// All strings should be null terminated.
+pub enum PrefDescriptor<'a> {
+    Filename {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+    },
+    Directory {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+    },
+    Bool {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+        default: bool,
+    },
+    Uint {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+        default: u32,
+    },
+    String {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+        default: &'a str,
+    },
+    Enum {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+        values: &'a [PrefEnumValue<'a>],
+        default: i32,
+        radio_buttons: bool,
+    },
+}
+
+/// Single variant of the enum preference.
+pub struct PrefEnumValue<'a> {
+    pub name: &'a str,
+    pub description: &'a str,
+    pub value: i32,
+}
+
+impl<'a> PrefDescriptor<'a> {
+    /// The name of the preference without the terminating null.
+    fn name(&self) -> &'a str {
+        let name = match self {
+            &PrefDescriptor::Filename { name, .. } => name,
+            &PrefDescriptor::Directory { name, .. } => name,
+            &PrefDescriptor::Bool { name, .. } => name,
+            &PrefDescriptor::Uint { name, .. } => name,
+            &PrefDescriptor::String { name, .. } => name,
+            &PrefDescriptor::Enum { name, .. } => name,
+        };
+        name.trim_end_matches('\0')
+    }
+
+    /// Initial value of the storage.
+    fn storage(&self) -> PrefStorage {
+        match self {
+            &PrefDescriptor::Filename { .. } | &PrefDescriptor::Directory { .. } => {
+                PrefStorage::Str(ptr::null())
+            },
+            &PrefDescriptor::Bool { default, .. } => PrefStorage::Bool(default as _),
+            &PrefDescriptor::Uint { default, .. } => PrefStorage::Uint(default),
+            &PrefDescriptor::String { default, .. } => PrefStorage::Str(default.as_ptr() as _),
+            &PrefDescriptor::Enum { default, .. } => PrefStorage::Enum(default),
+        }
+    }
+
+    /// The wireshark requires the array of variants terminated by the null variant.
+    fn enum_values(&self) -> Vec<sys::enum_val_t> {
+        match self {
+            &PrefDescriptor::Enum { values, .. } => values
+                .iter()
+                .map(|v| sys::enum_val_t {
+                    name: v.name.as_ptr() as _,
+                    description: v.description.as_ptr() as _,
+                    value: v.value,
+                })
+                .chain(std::iter::once(sys::enum_val_t {
+                    name: ptr::null(),
+                    description: ptr::null(),
+                    value: 0,
+                }))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The memory the wireshark writes the value of the preference.
+pub(crate) enum PrefStorage {
+    Str(*const c_char),
+    Bool(sys::gboolean),
+    Uint(sys::guint),
+    Enum(sys::gint),
+}
+
+/// Holds the storage for all preferences of the plugin,
+/// must not be moved or resized after registration,
+/// because the wireshark holds pointers on the elements.
+pub(crate) struct PrefsStorage {
+    values: Vec<PrefStorage>,
+    enum_values: Vec<Vec<sys::enum_val_t>>,
+}
+
+impl PrefsStorage {
+    pub const EMPTY: Self = PrefsStorage {
+        values: Vec::new(),
+        enum_values: Vec::new(),
+    };
+
+    /// Allocate the storage and register each preference in the wireshark.
+    pub fn register(&mut self, prefs: *mut sys::module_t, descriptors: &[PrefDescriptor<'_>]) {
+        self.values = descriptors.iter().map(PrefDescriptor::storage).collect();
+        self.enum_values = descriptors.iter().map(PrefDescriptor::enum_values).collect();
+
+        let storage = self.values.iter_mut().zip(self.enum_values.iter());
+        for (d, (value, enum_values)) in descriptors.iter().zip(storage) {
+            match (d, value) {
+                (
+                    &PrefDescriptor::Filename {
+                        name,
+                        title,
+                        description,
+                    },
+                    &mut PrefStorage::Str(ref mut value),
+                ) => unsafe {
+                    sys::prefs_register_filename_preference(
+                        prefs,
+                        name.as_ptr() as _,
+                        title.as_ptr() as _,
+                        description.as_ptr() as _,
+                        value,
+                        0,
+                    );
+                },
+                (
+                    &PrefDescriptor::Directory {
+                        name,
+                        title,
+                        description,
+                    },
+                    &mut PrefStorage::Str(ref mut value),
+                ) => unsafe {
+                    sys::prefs_register_directory_preference(
+                        prefs,
+                        name.as_ptr() as _,
+                        title.as_ptr() as _,
+                        description.as_ptr() as _,
+                        value,
+                    );
+                },
+                (
+                    &PrefDescriptor::Bool {
+                        name,
+                        title,
+                        description,
+                        ..
+                    },
+                    &mut PrefStorage::Bool(ref mut value),
+                ) => unsafe {
+                    sys::prefs_register_bool_preference(
+                        prefs,
+                        name.as_ptr() as _,
+                        title.as_ptr() as _,
+                        description.as_ptr() as _,
+                        value,
+                    );
+                },
+                (
+                    &PrefDescriptor::Uint {
+                        name,
+                        title,
+                        description,
+                        ..
+                    },
+                    &mut PrefStorage::Uint(ref mut value),
+                ) => unsafe {
+                    sys::prefs_register_uint_preference(
+                        prefs,
+                        name.as_ptr() as _,
+                        title.as_ptr() as _,
+                        description.as_ptr() as _,
+                        10,
+                        value,
+                    );
+                },
+                (
+                    &PrefDescriptor::String {
+                        name,
+                        title,
+                        description,
+                        ..
+                    },
+                    &mut PrefStorage::Str(ref mut value),
+                ) => unsafe {
+                    sys::prefs_register_string_preference(
+                        prefs,
+                        name.as_ptr() as _,
+                        title.as_ptr() as _,
+                        description.as_ptr() as _,
+                        value,
+                    );
+                },
+                (
+                    &PrefDescriptor::Enum {
+                        name,
+                        title,
+                        description,
+                        radio_buttons,
+                        ..
+                    },
+                    &mut PrefStorage::Enum(ref mut value),
+                ) => unsafe {
+                    sys::prefs_register_enum_preference(
+                        prefs,
+                        name.as_ptr() as _,
+                        title.as_ptr() as _,
+                        description.as_ptr() as _,
+                        value,
+                        enum_values.as_ptr(),
+                        radio_buttons as _,
+                    );
+                },
+                _ => unreachable!("the storage is created from the descriptor"),
+            }
+        }
+    }
+
+    /// Read the current values the wireshark wrote.
+    pub fn read<'a>(&self, descriptors: &[PrefDescriptor<'a>]) -> Preferences<'a> {
+        fn to_str<'a>(p: *const c_char) -> &'a str {
+            if p.is_null() {
+                ""
+            } else {
+                unsafe { CStr::from_ptr(p) }.to_str().unwrap_or("")
+            }
+        }
+
+        let values = descriptors
+            .iter()
+            .zip(self.values.iter())
+            .map(|(d, value)| {
+                let value = match (d, value) {
+                    (&PrefDescriptor::Filename { .. }, &PrefStorage::Str(p)) => {
+                        PrefValue::Filename(to_str(p))
+                    },
+                    (&PrefDescriptor::Directory { .. }, &PrefStorage::Str(p)) => {
+                        PrefValue::Directory(to_str(p))
+                    },
+                    (&PrefDescriptor::String { .. }, &PrefStorage::Str(p)) => {
+                        PrefValue::String(to_str(p))
+                    },
+                    (_, &PrefStorage::Bool(b)) => PrefValue::Bool(b != 0),
+                    (_, &PrefStorage::Uint(u)) => PrefValue::Uint(u),
+                    (_, &PrefStorage::Enum(e)) => PrefValue::Enum(e),
+                    _ => unreachable!("the storage is created from the descriptor"),
+                };
+                (d.name(), value)
+            })
+            .collect();
+        Preferences { values }
+    }
+}
+
+/// The value of the preference.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrefValue<'a> {
+    Filename(&'a str),
+    Directory(&'a str),
+    Bool(bool),
+    Uint(u32),
+    String(&'a str),
+    Enum(i32),
+}
+
+/// Current values of all preferences of the dissector,
+/// accessible by the name of the preference without the terminating null.
+#[derive(Debug, Clone)]
+pub struct Preferences<'a> {
+    values: Vec<(&'a str, PrefValue<'a>)>,
+}
+
+impl<'a> Preferences<'a> {
+    pub fn get(&self, name: &str) -> Option<&PrefValue<'a>> {
+        self.values
+            .iter()
+            .find(|&&(ref n, _)| *n == name)
+            .map(|&(_, ref value)| value)
+    }
+
+    pub fn filename(&self, name: &str) -> Option<&'a str> {
+        match self.get(name) {
+            Some(&PrefValue::Filename(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn directory(&self, name: &str) -> Option<&'a str> {
+        match self.get(name) {
+            Some(&PrefValue::Directory(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn bool(&self, name: &str) -> Option<bool> {
+        match self.get(name) {
+            Some(&PrefValue::Bool(b)) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn uint(&self, name: &str) -> Option<u32> {
+        match self.get(name) {
+            Some(&PrefValue::Uint(u)) => Some(u),
+            _ => None,
+        }
+    }
+
+    pub fn string(&self, name: &str) -> Option<&'a str> {
+        match self.get(name) {
+            Some(&PrefValue::String(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn enumeration(&self, name: &str) -> Option<i32> {
+        match self.get(name) {
+            Some(&PrefValue::Enum(e)) => Some(e),
+            _ => None,
+        }
+    }
+}
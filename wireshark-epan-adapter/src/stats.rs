@@ -0,0 +1,82 @@
+use std::{iter, mem, os::raw::c_char, ptr, slice};
+use crate::sys;
+
+/// Describes the statistics tree, the dissector declares it when creating the plugin.
+/// The abbrev is the name for `tshark -z <abbrev>,tree`, the name is shown
+/// in the Statistics menu. All strings should be null terminated.
+pub struct StatsDescriptor<'a> {
+    pub abbrev: &'a str,
+    pub name: &'a str,
+}
+
+/// The record the dissector queues to the tap, lives in the packet scope,
+/// the wireshark frees it when the packet is processed.
+/// Each path is the chain of the nodes of the statistics tree,
+/// every node on the path counts the record and collects the average of the value.
+pub(crate) struct TapRecord {
+    paths: *const TapPath,
+    paths_length: usize,
+    value: i32,
+}
+
+struct TapPath {
+    nodes: *const *const c_char,
+    nodes_length: usize,
+}
+
+/// Copy the items in the packet scope, the items should not need drop.
+fn packet_scope_slice<T>(items: impl ExactSizeIterator<Item = T>) -> *const T {
+    let length = items.len();
+    // the wireshark returns null for the empty allocation, but the slice requires non null
+    if length == 0 {
+        return ptr::NonNull::dangling().as_ptr();
+    }
+    let p = unsafe { sys::wmem_alloc(sys::wmem_packet_scope(), mem::size_of::<T>() * length) };
+    let p = p as *mut T;
+    for (i, item) in items.enumerate() {
+        unsafe { ptr::write(p.add(i), item) };
+    }
+    p
+}
+
+impl TapRecord {
+    /// Allocate the record in the packet scope.
+    pub fn new(paths: &[&[&str]], value: i32) -> *const Self {
+        let paths = paths.iter().map(|path| TapPath {
+            nodes: packet_scope_slice(path.iter().map(|node| {
+                let node = format!("{}\0", node);
+                unsafe { sys::wmem_strdup(sys::wmem_packet_scope(), node.as_ptr() as _) as _ }
+            })),
+            nodes_length: path.len(),
+        });
+        let record = TapRecord {
+            paths_length: paths.len(),
+            paths: packet_scope_slice(paths),
+            value,
+        };
+        packet_scope_slice(iter::once(record))
+    }
+
+    /// Count the record in the statistics tree.
+    pub fn tick(&self, st: *mut sys::stats_tree) {
+        let paths = unsafe { slice::from_raw_parts(self.paths, self.paths_length) };
+        for path in paths {
+            let nodes = unsafe { slice::from_raw_parts(path.nodes, path.nodes_length) };
+            let mut parent = 0;
+            for (i, &node) in nodes.iter().enumerate() {
+                // only the last node of the path has no children
+                let with_children = i + 1 < nodes.len();
+                parent = unsafe {
+                    sys::stats_tree_manip_node_int(
+                        sys::manip_node_mode_MN_AVERAGE,
+                        st,
+                        node,
+                        parent,
+                        with_children as _,
+                        self.value,
+                    )
+                };
+            }
+        }
+    }
+}